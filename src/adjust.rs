@@ -0,0 +1,233 @@
+// Adjusting the tones and colors of the image before it's turned into
+// characters. With only a handful of characters or colors to work with, a
+// picture that's a bit too dark or flat loses most of its detail, so these
+// are the usual photo editing controls for fixing that up.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use image::{self, DynamicImage};
+
+// How much of the darkest and lightest pixels auto levels ignores, so a few
+// stray pixels don't stop the stretch.
+const AUTO_LEVELS_CLIP: f32 = 0.005;
+// How many tiles CLAHE splits the image into along each side, and how much
+// the contrast in each tile is allowed to go up.
+const CLAHE_TILES: u32 = 8;
+const CLAHE_LIMIT: f32 = 3.0;
+
+pub struct Adjustments<'a> {
+    // Added to every channel, from -100 to 100 percent.
+    pub brightness: f32,
+    // From -100 (flat grey) to 100 percent, around the middle grey.
+    pub contrast: f32,
+    // Values over 1 brighten the dark tones, values under 1 darken them.
+    pub gamma: f32,
+    // 0 for grey, 1 for unchanged, more for stronger colors.
+    pub saturation: f32,
+    // The input values that become black and white.
+    pub levels: Option<(u8, u8)>,
+    // Stretch the tones so the darkest pixel is black and the lightest white.
+    pub auto_levels: bool,
+    // Histogram equalization, either "global" or "clahe".
+    pub equalize: Option<&'a str>,
+}
+
+// Apply the adjustments to an image. Levels come first since they work on
+// the image as it came in, then equalization, then the tone curve made from
+// brightness, contrast and gamma, and finally saturation.
+pub fn apply(img: DynamicImage, adj: &Adjustments) -> DynamicImage {
+    let mut img = img.to_rgba();
+    let (width, height) = img.dimensions();
+
+    let mut black = 0.0;
+    let mut white = 255.0;
+    if let Some((b, w)) = adj.levels {
+        black = b as f32;
+        white = w as f32;
+    } else if adj.auto_levels {
+        let (b, w) = find_levels(&luma(&img));
+        black = b as f32;
+        white = w as f32;
+    }
+    if white <= black {
+        white = black + 1.0;
+    }
+
+    // The tone curves only depend on the value of each channel, so they can
+    // be worked out once for every value.
+    let mut levels_curve = [0u8; 256];
+    for (v, out) in levels_curve.iter_mut().enumerate() {
+        let x = (v as f32 - black) / (white - black);
+        *out = (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    apply_curve(&mut img, &levels_curve);
+
+    // Equalizing works on the brightness of each pixel and moves all three
+    // channels by the same amount, so the colors stay about the same.
+    if let Some(method) = adj.equalize {
+        let before = luma(&img);
+        let after = match method {
+            "clahe" => equalize(&before, width, height, CLAHE_TILES, Some(CLAHE_LIMIT)),
+            _ => equalize(&before, width, height, 1, None),
+        };
+        for (i, pixel) in img.pixels_mut().enumerate() {
+            let shift = after[i] as f32 - before[i] as f32;
+            for c in 0..3 {
+                pixel.data[c] = (pixel.data[c] as f32 + shift).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    let contrast = (100.0 + adj.contrast.clamp(-100.0, 100.0)) / 100.0;
+    let mut curve = [0u8; 256];
+    for (v, out) in curve.iter_mut().enumerate() {
+        let mut x = v as f32 / 255.0 + adj.brightness / 100.0;
+        x = (x - 0.5) * contrast + 0.5;
+        x = x.clamp(0.0, 1.0).powf(1.0 / adj.gamma.max(0.01));
+        *out = (x * 255.0).round() as u8;
+    }
+    apply_curve(&mut img, &curve);
+
+    if adj.saturation != 1.0 {
+        for pixel in img.pixels_mut() {
+            let grey = pixel_luma(&pixel.data);
+            for c in 0..3 {
+                let v = grey + (pixel.data[c] as f32 - grey) * adj.saturation;
+                pixel.data[c] = v.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    image::ImageRgba8(img)
+}
+
+// Change the red, green and blue of every pixel using a lookup table.
+fn apply_curve(img: &mut image::RgbaImage, curve: &[u8; 256]) {
+    for pixel in img.pixels_mut() {
+        for c in 0..3 {
+            pixel.data[c] = curve[pixel.data[c] as usize];
+        }
+    }
+}
+
+// The brightness of a pixel, weighted the same way as in term.rs.
+fn pixel_luma(data: &[u8; 4]) -> f32 {
+    0.299*(data[0] as f32) + 0.587*(data[1] as f32) + 0.114*(data[2] as f32)
+}
+
+fn luma(img: &image::RgbaImage) -> Vec<u8> {
+    img.pixels().map(|p| pixel_luma(&p.data).round() as u8).collect()
+}
+
+// Find the black and white points for auto levels from the histogram.
+fn find_levels(luma: &[u8]) -> (u8, u8) {
+    let mut histogram = [0usize; 256];
+    for &v in luma {
+        histogram[v as usize] += 1;
+    }
+    let clip = (luma.len() as f32 * AUTO_LEVELS_CLIP) as usize;
+
+    let mut black = 0;
+    let mut count = 0;
+    for (v, &n) in histogram.iter().enumerate() {
+        count += n;
+        if count > clip {
+            black = v;
+            break;
+        }
+    }
+    let mut white = 255;
+    count = 0;
+    for (v, &n) in histogram.iter().enumerate().rev() {
+        count += n;
+        if count > clip {
+            white = v;
+            break;
+        }
+    }
+    (black as u8, white as u8)
+}
+
+// Histogram equalization, which spreads the tones out so each one is used
+// about as much as the others. With more than one tile this is CLAHE: every
+// tile gets its own mapping, limited so flat areas don't turn into noise,
+// and each pixel blends the mappings of the four tiles around it so there
+// are no seams.
+fn equalize(luma: &[u8], width: u32, height: u32, tiles: u32, limit: Option<f32>) -> Vec<u8> {
+    let tiles_x = tiles.min(width).max(1);
+    let tiles_y = tiles.min(height).max(1);
+
+    let mut maps = Vec::new();
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let (x0, x1) = (tx*width / tiles_x, (tx + 1)*width / tiles_x);
+            let (y0, y1) = (ty*height / tiles_y, (ty + 1)*height / tiles_y);
+            let mut histogram = [0.0f32; 256];
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    histogram[luma[(y*width + x) as usize] as usize] += 1.0;
+                }
+            }
+            let count = ((x1 - x0)*(y1 - y0)) as f32;
+
+            // Cut the peaks of the histogram off and share what was cut off
+            // between all the tones.
+            if let Some(limit) = limit {
+                let most = (limit * count / 256.0).max(1.0);
+                let mut excess = 0.0;
+                for n in histogram.iter_mut() {
+                    if *n > most {
+                        excess += *n - most;
+                        *n = most;
+                    }
+                }
+                for n in histogram.iter_mut() {
+                    *n += excess / 256.0;
+                }
+            }
+
+            let mut map = [0u8; 256];
+            let mut total = 0.0;
+            for v in 0..256 {
+                total += histogram[v];
+                map[v] = (255.0 * total / count).round().clamp(0.0, 255.0) as u8;
+            }
+            maps.push(map);
+        }
+    }
+
+    // Where a pixel is between the middles of the tiles, as a tile number
+    // and how far it is to the next one.
+    let locate = |pos: u32, size: u32, tiles: u32| -> (u32, u32, f32) {
+        let f = ((pos as f32 + 0.5) / size as f32 * tiles as f32 - 0.5).max(0.0);
+        let first = (f.floor() as u32).min(tiles - 1);
+        let second = (first + 1).min(tiles - 1);
+        (first, second, (f - first as f32).min(1.0))
+    };
+
+    let mut out = Vec::with_capacity(luma.len());
+    for y in 0..height {
+        let (ty0, ty1, ay) = locate(y, height, tiles_y);
+        for x in 0..width {
+            let (tx0, tx1, ax) = locate(x, width, tiles_x);
+            let v = luma[(y*width + x) as usize] as usize;
+            let at = |tx: u32, ty: u32| maps[(ty*tiles_x + tx) as usize][v] as f32;
+            let top = at(tx0, ty0) * (1.0 - ax) + at(tx1, ty0) * ax;
+            let bottom = at(tx0, ty1) * (1.0 - ax) + at(tx1, ty1) * ax;
+            out.push((top * (1.0 - ay) + bottom * ay).round() as u8);
+        }
+    }
+    out
+}
@@ -0,0 +1,307 @@
+// Animations: decoding the frames of animated GIFs and of image sequences,
+// and playing frames in the terminal by drawing each one over the last.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use gif::{self, SetParameter};
+use image::{self, DynamicImage, RgbaImage};
+use grid::{self, Grid};
+
+// Browsers show GIF frames with no delay (or a tiny one) for this long,
+// and GIFs are made to look right in browsers.
+const DEFAULT_DELAY: f64 = 100.0;
+
+// How many frames of an image sequence to decode ahead of the one being
+// shown.
+const PREFETCH: usize = 8;
+
+pub struct Frame {
+    pub image: DynamicImage,
+    // How long to show the frame for, in milliseconds.
+    pub delay: f64,
+}
+
+// Check whether a file is a GIF by its signature, since a GIF with the
+// wrong extension opens fine as a still image.
+pub fn is_gif(path: &str) -> bool {
+    let mut signature = [0; 6];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut signature)) {
+        Ok(()) => &signature == b"GIF87a" || &signature == b"GIF89a",
+        Err(_) => false,
+    }
+}
+
+// Decode every frame of a GIF. Frames in a GIF usually only cover the part
+// of the picture that changed, so each one is drawn onto a canvas the size
+// of the whole picture, which is then cleared or put back afterwards if the
+// frame says so.
+pub fn load_gif(path: &str) -> Result<Vec<Frame>, String> {
+    let file = File::open(path).map_err(|e| format!("Opening {} failed: {}", path, e))?;
+    let mut decoder = gif::Decoder::new(file);
+    decoder.set(gif::ColorOutput::RGBA);
+    let mut reader = decoder.read_info().map_err(|e| format!("Reading {} failed: {}", path, e))?;
+
+    let mut canvas = RgbaImage::new(reader.width() as u32, reader.height() as u32);
+    let mut frames = Vec::new();
+    loop {
+        let frame = match reader.read_next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            // Show what we got of a broken GIF, like browsers do.
+            Err(e) if !frames.is_empty() => {
+                eprintln!("Reading {} failed after {} frames: {}", path, frames.len(), e);
+                break;
+            },
+            Err(e) => return Err(format!("Reading {} failed: {}", path, e)),
+        };
+
+        let previous = if frame.dispose == gif::DisposalMethod::Previous { Some(canvas.clone()) } else { None };
+        let (left, top) = (frame.left as u32, frame.top as u32);
+        for (i, pixel) in frame.buffer.chunks(4).enumerate() {
+            let (x, y) = (left + i as u32 % frame.width as u32, top + i as u32 / frame.width as u32);
+            // Transparent pixels let the frames underneath show through.
+            if pixel[3] != 0 && x < canvas.width() && y < canvas.height() {
+                canvas.get_pixel_mut(x, y).data.copy_from_slice(pixel);
+            }
+        }
+
+        let delay = frame.delay as f64 * 10.0;
+        frames.push(Frame {
+            image: DynamicImage::ImageRgba8(canvas.clone()),
+            delay: if delay <= 10.0 { DEFAULT_DELAY } else { delay },
+        });
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame.height as u32).min(canvas.height()) {
+                    for x in left..(left + frame.width as u32).min(canvas.width()) {
+                        canvas.get_pixel_mut(x, y).data = [0, 0, 0, 0];
+                    }
+                }
+            },
+            gif::DisposalMethod::Previous => canvas = previous.unwrap(),
+            _ => {},
+        }
+    }
+    Ok(frames)
+}
+
+// The frames of an image sequence, which are decoded on another thread so
+// the next few are ready by the time they're needed.
+pub struct Sequence {
+    frames: Receiver<Frame>,
+}
+
+impl Iterator for Sequence {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        self.frames.recv().ok()
+    }
+}
+
+// Play every image in a directory in order, at some number of frames a
+// second. Files that aren't images are skipped.
+pub fn load_sequence(dir: &str, fps: f64) -> Result<Sequence, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Can't read the directory {}: {}", dir, e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && !p.file_name().and_then(|n| n.to_str()).unwrap_or(".").starts_with('.'))
+        .collect();
+    if paths.is_empty() {
+        return Err(format!("There aren't any files in {}.", dir));
+    }
+    paths.sort_by(|a, b| natural_order(&a.to_string_lossy(), &b.to_string_lossy()));
+
+    // The channel only holds a few frames, so the thread waits for us to
+    // catch up instead of decoding the whole sequence into memory.
+    let (sender, receiver) = mpsc::sync_channel(PREFETCH);
+    let delay = 1000.0 / fps;
+    thread::spawn(move || {
+        for path in paths {
+            match image::open(&path) {
+                Ok(image) => {
+                    if sender.send(Frame { image, delay }).is_err() {
+                        // Nobody's watching anymore.
+                        return;
+                    }
+                },
+                Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+            }
+        }
+    });
+    Ok(Sequence { frames: receiver })
+}
+
+// Compare file names the way people count, so frame_2 comes before
+// frame_10. Runs of digits are compared as numbers and everything else
+// character by character.
+fn natural_order(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().cloned(), b.peek().cloned()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut m = String::new();
+                while let Some(&c) = a.peek().filter(|c| c.is_ascii_digit()) {
+                    m.push(c);
+                    a.next();
+                }
+                let mut n = String::new();
+                while let Some(&c) = b.peek().filter(|c| c.is_ascii_digit()) {
+                    n.push(c);
+                    b.next();
+                }
+                // Leading zeros don't change a number, but a longer number
+                // without them is a bigger one.
+                let (m, n) = (m.trim_start_matches('0'), n.trim_start_matches('0'));
+                let order = m.len().cmp(&n.len()).then_with(|| m.cmp(n));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            },
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            },
+        }
+    }
+}
+
+// Shows frames one after another in the same place, by moving the cursor
+// back up over the last frame before drawing the next. Everything printed
+// is kept with the time it was printed at if we're recording.
+pub struct Player {
+    // Where the frames go, which is the terminal unless we're writing them
+    // to a file.
+    out: Box<dyn Write>,
+    // How many lines the last frame took up.
+    lines: u32,
+    // When the next frame is due, in seconds from the start.
+    clock: f64,
+    started: Instant,
+    // Whether to wait between frames. Nobody's watching if the output is
+    // going to a file or a pipe, so then it goes as fast as it can.
+    wait: bool,
+    // Whether frames that are already late can be left out. That's only
+    // worth it for live video, which keeps coming whether we're ready or
+    // not. Everything else would rather be slow than lose frames, and so
+    // would a recording.
+    drop_late: bool,
+    record: bool,
+    pub events: Vec<(f64, String)>,
+}
+
+impl Player {
+    pub fn new(out: Box<dyn Write>, wait: bool, record: bool, live: bool) -> Player {
+        Player {
+            out,
+            lines: 0,
+            clock: 0.0,
+            started: Instant::now(),
+            wait,
+            drop_late: live && !record,
+            record,
+            events: Vec::new(),
+        }
+    }
+
+    // Whether we've fallen so far behind that the time to show the next
+    // frame has already gone by. Skipping it is the only way to catch up.
+    pub fn behind(&self, delay: f64) -> bool {
+        self.drop_late && self.wait && self.started.elapsed() > seconds(self.clock + delay / 1000.0)
+    }
+
+    // Leave out a frame, but keep time as if it had been shown.
+    pub fn skip(&mut self, delay: f64) {
+        self.clock += delay / 1000.0;
+    }
+
+    // Show a frame, then wait until it's time for the next one.
+    pub fn show(&mut self, grid: &Grid, delay: f64) {
+        let mut text = String::new();
+        if self.lines > 0 {
+            text.push_str(&format!("\x1b[{}A", self.lines));
+        }
+        text.push_str(&grid::to_ansi(grid));
+        // Clear whatever's left below, in case the last frame was taller.
+        text.push_str("\x1b[J");
+        // Someone closing the pipe we're writing to isn't worth a message.
+        let _ = self.out.write_all(text.as_bytes()).and_then(|_| self.out.flush());
+
+        if self.record {
+            self.events.push((self.clock, text));
+        }
+        self.lines = grid.height;
+        self.clock += delay / 1000.0;
+
+        if self.wait {
+            let due = seconds(self.clock);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                thread::sleep(due - elapsed);
+            }
+        }
+    }
+}
+
+fn seconds(time: f64) -> Duration {
+    Duration::from_millis((time * 1000.0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        names.sort_by(|a, b| natural_order(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_go_in_counting_order() {
+        assert_eq!(sorted(&["frame10", "frame2", "frame1", "frame100"]), vec!["frame1", "frame2", "frame10", "frame100"]);
+        assert_eq!(natural_order("9.png", "10.png"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_dont_matter() {
+        assert_eq!(natural_order("frame007", "frame7"), Ordering::Equal);
+        assert_eq!(natural_order("frame010", "frame9"), Ordering::Greater);
+        assert_eq!(sorted(&["img_0010", "img_009", "img_1"]), vec!["img_1", "img_009", "img_0010"]);
+    }
+
+    #[test]
+    fn text_and_numbers_mix() {
+        assert_eq!(sorted(&["b1", "a10", "a2", "a2b", "a2a", "a"]), vec!["a", "a2", "a2a", "a2b", "a10", "b1"]);
+        assert_eq!(sorted(&["shot2_10", "shot2_9", "shot10_1"]), vec!["shot2_9", "shot2_10", "shot10_1"]);
+        assert_eq!(natural_order("frame", "frame"), Ordering::Equal);
+        assert_eq!(natural_order("frame", "frame1"), Ordering::Less);
+    }
+}
@@ -0,0 +1,124 @@
+// Character ramps for the ASCII art styles. A ramp is a list of characters
+// sorted by how much ink they put on the screen, along with the shade of grey
+// that each character stands in for.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use exoquant::Color;
+use font;
+
+// The built-in ramps. Like the --charset option, these go from the least ink
+// to the most. The first three are the original hand-tuned styles, so they
+// keep their original grey levels instead of evenly spaced ones.
+const NAMED_RAMPS: [(&str, &str, Option<&[u8]>); 6] = [
+    ("ascii", "  -':;LOW", Some(&[255, 224, 192, 160, 128, 96, 64, 32, 0])),
+    ("ascii-simple", " :oOW", Some(&[255, 160, 128, 64, 0])),
+    ("pound", " #", Some(&[255, 0])),
+    ("blocks", " \u{2591}\u{2592}\u{2593}\u{2588}", None),
+    ("bourke", " .'`^\",:;Il!i><~+_-?][}{1)(|\\/tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$", None),
+    ("bourke10", " .:-=+*#%@", None),
+];
+
+// The names of the built-in ramps, for the argument parser.
+pub const RAMP_NAMES: [&str; 6] = ["ascii", "ascii-simple", "pound", "blocks", "bourke", "bourke10"];
+
+pub struct Ramp {
+    // The characters, from the most ink to the least. This is backwards from
+    // how people write ramps but it matches the palette, which goes from
+    // black to white.
+    pub glyphs: Vec<char>,
+    pub palette: Vec<Color>,
+}
+
+impl Ramp {
+    // Make a ramp out of a string of characters going from the least ink to
+    // the most, with evenly spaced shades of grey.
+    pub fn from_chars(chars: &str) -> Result<Ramp, String> {
+        let mut glyphs: Vec<char> = chars.chars().collect();
+        if glyphs.len() < 2 {
+            return Err(format!("The character set `{}' needs at least two characters.", chars));
+        }
+        glyphs.reverse();
+
+        let n = glyphs.len() - 1;
+        let levels: Vec<u8> = (0..glyphs.len())
+            .map(|i| ((255*i + n/2) / n) as u8)
+            .collect();
+
+        Ok(Ramp { glyphs, palette: grey_palette(&levels) })
+    }
+
+    // Look up one of the built-in ramps by name.
+    pub fn named(name: &str) -> Result<Ramp, String> {
+        for &(ramp_name, chars, levels) in NAMED_RAMPS.iter() {
+            if ramp_name == name {
+                let mut ramp = Ramp::from_chars(chars)?;
+                if let Some(levels) = levels {
+                    let mut levels = levels.to_vec();
+                    levels.reverse();
+                    ramp.palette = grey_palette(&levels);
+                }
+                return Ok(ramp);
+            }
+        }
+        Err(format!("There is no built-in character ramp called `{}'.", name))
+    }
+
+    // Swap the characters around so that black gets the least ink and white
+    // gets the most, for light text on a dark background. Every character
+    // keeps its own shade, just flipped, since the shades aren't always
+    // evenly spaced. Both lists are then turned around so the palette still
+    // goes from black to white.
+    pub fn invert(&mut self) {
+        for c in self.palette.iter_mut() {
+            c.r = 255 - c.r;
+            c.g = 255 - c.g;
+            c.b = 255 - c.b;
+        }
+        self.glyphs.reverse();
+        self.palette.reverse();
+    }
+
+    // Replace the evenly spaced shades of grey with ones based on how much
+    // ink each character actually uses, measured with the built-in font. The
+    // character with the most ink becomes black and the one with the least
+    // becomes white. Fails if the font doesn't have one of the characters.
+    pub fn measure_ink(&mut self) -> Result<(), String> {
+        let mut coverage = Vec::new();
+        for &c in self.glyphs.iter() {
+            match font::coverage(c) {
+                Some(n) => coverage.push(n),
+                None => return Err(format!("Can't measure the ink of `{}', the built-in font doesn't have it.", c)),
+            }
+        }
+
+        let most = coverage.iter().cloned().fold(0.0, f32::max);
+        let least = coverage.iter().cloned().fold(1.0, f32::min);
+        if most <= least {
+            return Err("All of the characters use the same amount of ink.".to_string());
+        }
+
+        let levels: Vec<u8> = coverage.iter()
+            .map(|&c| (255.0 * (most - c) / (most - least)).round() as u8)
+            .collect();
+        self.palette = grey_palette(&levels);
+        Ok(())
+    }
+}
+
+// Turn a list of grey levels into a palette.
+fn grey_palette(levels: &[u8]) -> Vec<Color> {
+    levels.iter().map(|&v| Color { r: v, g: v, b: v, a: 255 }).collect()
+}
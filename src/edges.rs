@@ -0,0 +1,108 @@
+// Edge detection for the line art mode. Tonal ramps smear thin lines into
+// mush, so instead we find the edges in the image and draw each one with a
+// character that points the same way the edge does.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::f32;
+use image;
+use image::GenericImage;
+
+// Find the edges in an image and pick a character for every pixel that lies
+// on one. Pixels that aren't on an edge get None. The threshold is the
+// contrast (0-255) an edge needs before it gets drawn.
+pub fn detect_edges(img: &image::DynamicImage, threshold: f32) -> Vec<Option<char>> {
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as i64, height as i64);
+    let luma = img.to_luma();
+
+    // Look up the brightness of a pixel, clamping coordinates to the edge of
+    // the image so the border doesn't look like a giant edge.
+    let at = |x: i64, y: i64| -> f32 {
+        let cx = x.max(0).min(w - 1) as u32;
+        let cy = y.max(0).min(h - 1) as u32;
+        luma.get_pixel(cx, cy).data[0] as f32
+    };
+
+    // Sobel pass. We keep the gradient of every pixel because the thinning
+    // step below needs to look at the neighbours.
+    let mut gx = vec![0.0f32; (w*h) as usize];
+    let mut gy = vec![0.0f32; (w*h) as usize];
+    let mut mag = vec![0.0f32; (w*h) as usize];
+    for y in 0..h {
+        for x in 0..w {
+            let i = (w*y + x) as usize;
+            gx[i] = (at(x+1, y-1) + 2.0*at(x+1, y) + at(x+1, y+1))
+                - (at(x-1, y-1) + 2.0*at(x-1, y) + at(x-1, y+1));
+            gy[i] = (at(x-1, y+1) + 2.0*at(x, y+1) + at(x+1, y+1))
+                - (at(x-1, y-1) + 2.0*at(x, y-1) + at(x+1, y-1));
+            // A hard black to white step gives a magnitude of 4*255, so
+            // divide by 4 to put it on the same scale as the threshold.
+            mag[i] = (gx[i]*gx[i] + gy[i]*gy[i]).sqrt() / 4.0;
+        }
+    }
+
+    let mag_at = |x: i64, y: i64| -> f32 {
+        if x < 0 || y < 0 || x >= w || y >= h {
+            0.0
+        } else {
+            mag[(w*y + x) as usize]
+        }
+    };
+
+    let mut edges = Vec::with_capacity((w*h) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let i = (w*y + x) as usize;
+            if mag[i] < threshold {
+                edges.push(None);
+                continue;
+            }
+
+            // Sort the gradient direction into one of four bins. The angle is
+            // folded into 0-180 degrees since an edge looks the same from
+            // either side.
+            let mut angle = gy[i].atan2(gx[i]).to_degrees();
+            if angle < 0.0 {
+                angle += 180.0;
+            }
+            let (dx, dy, glyph) = if !(22.5..157.5).contains(&angle) {
+                // The brightness changes from left to right, so the edge
+                // itself is vertical.
+                (1, 0, '|')
+            } else if angle < 67.5 {
+                (1, 1, '/')
+            } else if angle < 112.5 {
+                // Horizontal edges sit at the bottom of the cell when the
+                // dark side is above them, which lines them up with the
+                // outline of dark shapes on a light background.
+                (0, 1, if gy[i] > 0.0 { '_' } else { '-' })
+            } else {
+                (1, -1, '\\')
+            };
+
+            // Non-maximum suppression, as in the Canny detector: only keep
+            // the pixel if it is the strongest one across the edge. This
+            // thins blurry edges down to a single character.
+            if mag[i] < mag_at(x + dx, y + dy) || mag[i] < mag_at(x - dx, y - dy) {
+                edges.push(None);
+            } else {
+                edges.push(Some(glyph));
+            }
+        }
+    }
+
+    edges
+}
@@ -0,0 +1,274 @@
+// ANSI art files (.ans), the format of BBS art and editors like PabloDraw
+// and Moebius. They're like river's normal output, but in the IBM PC
+// character set instead of UTF-8, limited to the 16 classic colors, and
+// usually with a SAUCE record on the end that says who made the picture
+// and how to show it.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use grid::Grid;
+use super::{Theme, nearest};
+
+// The top half of code page 437, the character set of the IBM PC. The bottom
+// half is the same as ASCII.
+pub const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+pub struct Sauce<'a> {
+    pub title: &'a str,
+    pub author: &'a str,
+}
+
+// Turn a grid into an ANSI art file. With wrap set every row is padded out
+// to exactly 80 columns and there are no line breaks, since ANSI art viewers
+// wrap at 80 columns by themselves; otherwise rows end with CR LF. A grid
+// that's wider than 80 columns is padded to 160, 240 and so on, so its rows
+// carry on onto the next lines instead of being cut off.
+pub fn to_ans(grid: &Grid, theme: &Theme, wrap: bool, sauce: Option<&Sauce>) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+    let width = if wrap { grid.width.max(1).div_ceil(80) * 80 } else { grid.width };
+    let mut bright_bg = false;
+
+    out.extend_from_slice(b"\x1b[0m");
+    for y in 0..grid.height {
+        let mut current = None;
+        for x in 0..width {
+            let (ch, fg, bg) = if x < grid.width {
+                let cell = grid.get(x, y);
+                (cell.ch, nearest(theme, 16, theme.fg(cell.fg)), nearest(theme, 16, theme.bg(cell.bg)))
+            } else {
+                (' ', nearest(theme, 16, theme.fg), nearest(theme, 16, theme.bg))
+            };
+
+            if current != Some((fg, bg)) {
+                // Bright text is bold, and bright backgrounds are blinking
+                // text, which viewers show as bright when iCE colors are on.
+                let mut sgr = String::from("\x1b[0");
+                if fg >= 8 {
+                    sgr.push_str(";1");
+                }
+                if bg >= 8 {
+                    sgr.push_str(";5");
+                    bright_bg = true;
+                }
+                sgr.push_str(&format!(";{};{}m", 30 + fg % 8, 40 + bg % 8));
+                out.extend_from_slice(sgr.as_bytes());
+                current = Some((fg, bg));
+            }
+            out.push(to_cp437(ch));
+        }
+        out.extend_from_slice(b"\x1b[0m");
+        if !wrap {
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+
+    if let Some(sauce) = sauce {
+        let size = out.len() as u32;
+        // SAUCE comes after an end of file character, so DOS programs that
+        // just print the file stop before it.
+        out.push(0x1a);
+        let (columns, lines) = if wrap { (80, grid.height * width / 80) } else { (width, grid.height) };
+        out.extend_from_slice(&sauce_record(sauce, size, columns, lines, bright_bg));
+    }
+    out
+}
+
+// Turn a character into its code page 437 byte, or a question mark if it
+// isn't in there.
+pub fn to_cp437(c: char) -> u8 {
+    if (' '..='~').contains(&c) {
+        return c as u8;
+    }
+    match CP437_HIGH.iter().position(|&h| h == c) {
+        Some(i) => 0x80 + i as u8,
+        None => b'?',
+    }
+}
+
+// Build the 128 byte SAUCE record. See the SAUCE spec for the layout; the
+// numbers are little-endian and the text fields are padded with spaces.
+fn sauce_record(sauce: &Sauce, size: u32, width: u32, height: u32, ice_colors: bool) -> Vec<u8> {
+    let mut record = Vec::with_capacity(128);
+    record.extend_from_slice(b"SAUCE00");
+    push_field(&mut record, sauce.title, 35, b' ');
+    push_field(&mut record, sauce.author, 20, b' ');
+    push_field(&mut record, "", 20, b' ');
+    record.extend_from_slice(today().as_bytes());
+    record.extend_from_slice(&size.to_le_bytes());
+    // Data type 1 (character) and file type 1 (ANSi).
+    record.push(1);
+    record.push(1);
+    record.extend_from_slice(&(width as u16).to_le_bytes());
+    record.extend_from_slice(&(height as u16).to_le_bytes());
+    record.extend_from_slice(&[0, 0, 0, 0]);
+    // No comments.
+    record.push(0);
+    // The flags: iCE colors, and 8 pixel wide letters.
+    record.push(if ice_colors { 0x01 } else { 0x00 } | 0x02);
+    push_field(&mut record, "IBM VGA", 22, 0);
+    record
+}
+
+// Add a text field of a fixed length to a SAUCE record.
+fn push_field(record: &mut Vec<u8>, text: &str, length: usize, padding: u8) {
+    let mut bytes: Vec<u8> = text.chars().map(to_cp437).take(length).collect();
+    bytes.resize(length, padding);
+    record.extend_from_slice(&bytes);
+}
+
+// Today's date as CCYYMMDD, worked out from the system clock since the
+// standard library doesn't do calendars.
+fn today() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    // Howard Hinnant's days-to-civil algorithm.
+    let z = (seconds / 86400) as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era*146097;
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era*400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::{Cell, Ink};
+
+    fn theme() -> Theme {
+        let mut palette: Vec<(u8, u8, u8)> = (0..16).map(|i| (i*16, 255 - i*16, i*8)).collect();
+        palette.resize(256, (0, 0, 0));
+        Theme { palette, fg: (240, 15, 120), bg: (0, 255, 0) }
+    }
+
+    // The characters of the picture, without the escape codes.
+    fn printed(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut escape = false;
+        for &b in data {
+            if b == 0x1b {
+                escape = true;
+            } else if escape {
+                escape = b != b'm';
+            } else {
+                out.push(b);
+            }
+        }
+        out
+    }
+
+    fn u16_at(data: &[u8], i: usize) -> u16 {
+        u16::from_le_bytes([data[i], data[i + 1]])
+    }
+
+    #[test]
+    fn sauce_record_layout() {
+        let sauce = Sauce { title: "A title", author: "Me" };
+        let record = sauce_record(&sauce, 0x01020304, 80, 25, true);
+        assert_eq!(record.len(), 128);
+        assert_eq!(&record[0..7], b"SAUCE00");
+        assert_eq!(&record[7..42], format!("{:35}", "A title").as_bytes());
+        assert_eq!(&record[42..62], format!("{:20}", "Me").as_bytes());
+        assert_eq!(&record[62..82], &[b' '; 20][..]);
+        assert!(record[82..90].iter().all(|b| b.is_ascii_digit()));
+        assert_eq!(&record[90..94], &[4, 3, 2, 1]);
+        // Character data, ANSi file.
+        assert_eq!((record[94], record[95]), (1, 1));
+        assert_eq!((u16_at(&record, 96), u16_at(&record, 98)), (80, 25));
+        assert_eq!(&record[100..105], &[0; 5]);
+        // iCE colors and 8 pixel letters.
+        assert_eq!(record[105], 0x03);
+        assert_eq!(&record[106..113], b"IBM VGA");
+        assert_eq!(&record[113..128], &[0; 15]);
+
+        assert_eq!(sauce_record(&sauce, 0, 80, 25, false)[105], 0x02);
+        // Long titles are cut off.
+        let long = Sauce { title: &"x".repeat(50), author: "" };
+        let record = sauce_record(&long, 0, 1, 1, false);
+        assert_eq!(&record[7..42], "x".repeat(35).as_bytes());
+        assert_eq!(record[42], b' ');
+    }
+
+    #[test]
+    fn sauce_goes_after_the_picture() {
+        let mut grid = Grid::new(3, 2);
+        grid.set(0, 0, Cell { ch: 'a', fg: Ink::Indexed(1), bg: Ink::Indexed(12) });
+        let sauce = Sauce { title: "t", author: "a" };
+        let data = to_ans(&grid, &theme(), false, Some(&sauce));
+        let (art, record) = data.split_at(data.len() - 128);
+        assert_eq!(art.last(), Some(&0x1a));
+        assert_eq!(&record[0..7], b"SAUCE00");
+        assert_eq!(u32::from_le_bytes([record[90], record[91], record[92], record[93]]) as usize, art.len() - 1);
+        assert_eq!((u16_at(record, 96), u16_at(record, 98)), (3, 2));
+        // The bright background needs iCE colors.
+        assert_eq!(record[105], 0x03);
+        assert!(art.windows(6).any(|w| w == b"\x1b[0;5;"));
+        assert_eq!(to_ans(&grid, &theme(), false, None).len(), art.len() - 1);
+    }
+
+    #[test]
+    fn rows_end_with_line_breaks_without_wrap() {
+        let grid = Grid::new(100, 2);
+        let data = to_ans(&grid, &theme(), false, None);
+        let text = printed(&data);
+        assert_eq!(text.len(), 2*(100 + 2));
+        assert_eq!(&text[100..102], b"\r\n");
+        assert_eq!(&text[202..], b"\r\n");
+    }
+
+    #[test]
+    fn wrapped_rows_are_padded_to_80_columns() {
+        for &(width, padded) in [(1, 80), (40, 80), (80, 80), (81, 160), (100, 160), (160, 160), (200, 240)].iter() {
+            let mut grid = Grid::new(width, 3);
+            for y in 0..3 {
+                grid.set(width - 1, y, Cell::glyph('#'));
+            }
+            let sauce = Sauce { title: "", author: "" };
+            let data = to_ans(&grid, &theme(), true, Some(&sauce));
+            let text = printed(&data[..data.len() - 129]);
+            assert_eq!(text.len(), 3*padded as usize, "{} columns", width);
+            assert!(!text.contains(&b'\n'));
+            for y in 0..3 {
+                let row = &text[(y*padded) as usize..((y + 1)*padded) as usize];
+                assert_eq!(row[width as usize - 1], b'#');
+                assert!(row[width as usize..].iter().all(|&b| b == b' '));
+            }
+            let record = &data[data.len() - 128..];
+            assert_eq!((u16_at(record, 96), u16_at(record, 98)), (80, 3*padded as u16/80), "{} columns", width);
+        }
+    }
+
+    #[test]
+    fn converts_to_code_page_437() {
+        assert_eq!(to_cp437('A'), b'A');
+        assert_eq!(to_cp437('\u{2588}'), 0xdb);
+        assert_eq!(to_cp437('\u{2591}'), 0xb0);
+        assert_eq!(to_cp437('\u{e9}'), 0x82);
+        assert_eq!(to_cp437('\u{4e00}'), b'?');
+    }
+}
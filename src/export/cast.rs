@@ -0,0 +1,35 @@
+// asciicast files, the recordings made by asciinema. Version 2 is a line of
+// JSON saying how big the terminal is, then a line for everything printed:
+// [seconds since the start, "o", "the text"]. They can be played back with
+// `asciinema play` or the asciinema web player.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use super::json_string;
+
+// Turn the output of an animation into an asciicast. The header leaves out
+// the time of the recording, so the same animation always makes the same
+// file. The terminal normally turns line feeds into CR LF for us, but
+// players write the text straight to their screen, so that's done here.
+pub fn to_cast(width: u32, height: u32, events: &[(f64, String)]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{{\"version\": 2, \"width\": {}, \"height\": {}}}", width, height);
+    for &(time, ref text) in events {
+        let _ = writeln!(out, "[{:.6}, \"o\", \"{}\"]", time, json_string(&text.replace('\n', "\r\n")));
+    }
+    out
+}
+
@@ -0,0 +1,207 @@
+// Output for pasting into chat and forums: mIRC color codes, BBCode and
+// Discord's ```ansi code blocks. None of them can do as many colors as a
+// terminal, so colors are matched to what each one has.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use grid::{Cell, Grid, Ink};
+use super::{Theme, distance, hex, nearest};
+
+// mIRC numbers its first 16 colors differently from terminals. This is the
+// mIRC number for each terminal color.
+const MIRC_FROM_ANSI: [u8; 16] = [1, 5, 3, 7, 2, 6, 10, 15, 14, 4, 9, 8, 12, 13, 11, 0];
+
+// The extended mIRC colors 16 to 98, which have fixed values.
+const MIRC_EXTENDED: [u32; 83] = [
+    0x470000, 0x472100, 0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747, 0x000047, 0x2e0047, 0x470047, 0x47002a,
+    0x740000, 0x743a00, 0x747400, 0x517400, 0x007400, 0x007449, 0x007474, 0x004074, 0x000074, 0x4b0074, 0x740074, 0x740045,
+    0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500, 0x00b571, 0x00b5b5, 0x0063b5, 0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b,
+    0xff0000, 0xff8c00, 0xffff00, 0xb2ff00, 0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff, 0xff0098,
+    0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff, 0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc,
+    0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c, 0x9cff9c, 0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3,
+    0x000000, 0x131313, 0x282828, 0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f, 0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+// The mIRC color that means "leave it as it is".
+const MIRC_DEFAULT: u8 = 99;
+
+// Discord only shows a message up to this many characters long.
+pub const DISCORD_LIMIT: usize = 2000;
+
+// The colors Discord draws for text colors 30 to 37 and backgrounds 40 to 47.
+// They're its own Solarized-like colors, whatever the terminal theme is, and
+// the backgrounds aren't the same as the text colors at all.
+const DISCORD_FG: [u32; 8] = [0x4f545c, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xffffff];
+const DISCORD_BG: [u32; 8] = [0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3];
+
+// Split a row of the grid into runs of cells that look the same according
+// to key.
+fn runs<K: PartialEq, F: Fn(Cell) -> K>(grid: &Grid, y: u32, key: F) -> Vec<(K, String)> {
+    let mut runs: Vec<(K, String)> = Vec::new();
+    for x in 0..grid.width {
+        let cell = grid.get(x, y);
+        let k = key(cell);
+        match runs.last_mut() {
+            Some(&mut (ref last, ref mut text)) if *last == k => text.push(cell.ch),
+            _ => runs.push((k, cell.ch.to_string())),
+        }
+    }
+    runs
+}
+
+// Turn a grid into text with mIRC color codes, using either the 16 basic
+// colors or all 99. Every color change is written as Ctrl-C with two digit
+// foreground and background numbers, so a digit or comma in the picture
+// can't be mistaken for part of the code.
+pub fn to_irc(grid: &Grid, theme: &Theme, extended: bool) -> String {
+    let code = |ink: Ink, resolved: (u8, u8, u8)| -> u8 {
+        match ink {
+            Ink::Default => MIRC_DEFAULT,
+            Ink::Indexed(i) if i < 16 => MIRC_FROM_ANSI[i as usize],
+            _ => mirc_color(theme, resolved, extended),
+        }
+    };
+
+    let mut out = String::new();
+    for y in 0..grid.height {
+        for ((fg, bg), text) in runs(grid, y, |c| (code(c.fg, theme.fg(c.fg)), code(c.bg, theme.bg(c.bg)))) {
+            if fg == MIRC_DEFAULT && bg == MIRC_DEFAULT {
+                // Ctrl-O puts everything back to normal.
+                out.push('\x0f');
+            } else {
+                out.push_str(&format!("\x03{:02},{:02}", fg, bg));
+            }
+            out.push_str(&text);
+        }
+        out.push_str("\x0f\n");
+    }
+    out
+}
+
+// Find the closest mIRC color to a color.
+fn mirc_color(theme: &Theme, color: (u8, u8, u8), extended: bool) -> u8 {
+    let basic = nearest(theme, 16, color);
+    if !extended {
+        return MIRC_FROM_ANSI[basic];
+    }
+    let mut best = MIRC_FROM_ANSI[basic];
+    let mut best_distance = distance(theme.palette[basic], color);
+    for (i, &value) in MIRC_EXTENDED.iter().enumerate() {
+        let d = distance(((value >> 16) as u8, (value >> 8) as u8, value as u8), color);
+        if d < best_distance {
+            best = 16 + i as u8;
+            best_distance = d;
+        }
+    }
+    best
+}
+
+// Turn a grid into BBCode. Most forums can't color the background of text,
+// so a space with a colored background becomes a full block in that color
+// instead, and other backgrounds are left out.
+pub fn to_bbcode(grid: &Grid, theme: &Theme) -> String {
+    let mut out = String::new();
+    let key = |c: Cell| -> (Option<(u8, u8, u8)>, bool) {
+        if c.ch == ' ' && c.bg != Ink::Default {
+            (Some(theme.bg(c.bg)), true)
+        } else if c.fg != Ink::Default {
+            (Some(theme.fg(c.fg)), false)
+        } else {
+            (None, false)
+        }
+    };
+    for y in 0..grid.height {
+        for ((color, block), text) in runs(grid, y, key) {
+            let text = if block { "\u{2588}".repeat(text.chars().count()) } else { text };
+            match color {
+                Some(color) => out.push_str(&format!("[color={}]{}[/color]", hex(color), text)),
+                None => out.push_str(&text),
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Turn a grid into a Discord ```ansi code block. Discord only knows eight
+// colors for text and eight for backgrounds, so everything is matched to
+// those.
+pub fn to_discord(grid: &Grid, theme: &Theme) -> String {
+    let basic = |ink: Ink, resolved: (u8, u8, u8), table: &[u32]| -> Option<usize> {
+        match ink {
+            Ink::Default => None,
+            _ => Some(closest(table, resolved)),
+        }
+    };
+    let key = |c: Cell| (basic(c.fg, theme.fg(c.fg), &DISCORD_FG), basic(c.bg, theme.bg(c.bg), &DISCORD_BG));
+
+    let mut out = String::from("```ansi\n");
+    for y in 0..grid.height {
+        let mut current = (None, None);
+        for (style, text) in runs(grid, y, key) {
+            if style != current {
+                let mut sgr = String::from("\x1b[0");
+                if let Some(fg) = style.0 {
+                    sgr.push_str(&format!(";{}", 30 + fg));
+                }
+                if let Some(bg) = style.1 {
+                    sgr.push_str(&format!(";{}", 40 + bg));
+                }
+                sgr.push('m');
+                out.push_str(&sgr);
+                current = style;
+            }
+            out.push_str(&text);
+        }
+        if current != (None, None) {
+            out.push_str("\x1b[0m");
+        }
+        out.push('\n');
+    }
+    out.push_str("```\n");
+    out
+}
+
+// Find the closest of some fixed colors to a color.
+fn closest(table: &[u32], color: (u8, u8, u8)) -> usize {
+    let mut best = 0;
+    let mut best_distance = i32::MAX;
+    for (i, &value) in table.iter().enumerate() {
+        let d = distance(((value >> 16) as u8, (value >> 8) as u8, value as u8), color);
+        if d < best_distance {
+            best = i;
+            best_distance = d;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discord_uses_its_own_colors() {
+        let theme = Theme { palette: vec![(0, 0, 0); 256], fg: (255, 255, 255), bg: (0, 0, 0) };
+        let mut grid = Grid::new(4, 1);
+        // Discord's dark blue background and its orange one, which is the
+        // closest it has to red.
+        grid.set(0, 0, Cell::block(Ink::Rgb(0, 40, 50)));
+        grid.set(1, 0, Cell::block(Ink::Rgb(255, 0, 0)));
+        grid.set(2, 0, Cell { ch: '#', fg: Ink::Rgb(220, 50, 47), bg: Ink::Default });
+        grid.set(3, 0, Cell::glyph('#'));
+        assert_eq!(to_discord(&grid, &theme), "```ansi\n\x1b[0;40m \x1b[0;41m \x1b[0;31m#\x1b[0m#\n```\n");
+    }
+}
@@ -0,0 +1,140 @@
+// The rendered grid as data, for other programs that want river's cells
+// instead of escape codes they'd have to pick apart again.
+//
+// The JSON looks like this, with one array per row:
+//
+//   {"width": 2, "height": 1, "mode": "256colors",
+//    "palette": ["#000000", ...], "foreground": "#c0c0c0", "background": "#000000",
+//    "rows": [[{"ch": "▀", "fg": 196, "bg": null}, {"ch": " ", "fg": null, "bg": "#ff8800"}]]}
+//
+// A color is null for the terminal's default, a number for a palette color
+// or "#rrggbb" for any other color.
+//
+// The binary form ("cells") has the same things in it. Every number is
+// little-endian, and the fields come one after another with nothing in
+// between:
+//
+//   magic           4 bytes    "RIVR"
+//   version         u8         1
+//   width, height   u16 each   in cells, so at most 65535 each way
+//   mode            u8 length, then that many bytes of the mode's name
+//   palette         u16 count, then r, g, b bytes for each color
+//   foreground      r, g, b    the terminal's default text color
+//   background      r, g, b    the terminal's default background color
+//   cells           width*height of them, row by row from the top left
+//
+// Each cell is the character as a u32, then its foreground and background.
+// A color starts with a byte saying what kind it is: 0 for the default with
+// nothing after it, 1 for a palette color with its number (u8) after it, or
+// 2 with r, g, b bytes after it.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use grid::{Grid, Ink};
+use super::{Theme, hex, json_string};
+
+// Turn a grid into JSON.
+pub fn to_json(grid: &Grid, theme: &Theme, mode: &str) -> String {
+    let palette: Vec<String> = theme.palette.iter().map(|&c| format!("\"{}\"", hex(c))).collect();
+    let mut out = String::new();
+    let _ = write!(out, "{{\"width\": {}, \"height\": {}, \"mode\": \"{}\",\n\"palette\": [{}],\n\"foreground\": \"{}\", \"background\": \"{}\",\n\"rows\": [",
+        grid.width, grid.height, json_string(mode), palette.join(", "), hex(theme.fg), hex(theme.bg));
+
+    for y in 0..grid.height {
+        let cells: Vec<String> = (0..grid.width).map(|x| {
+            let cell = grid.get(x, y);
+            format!("{{\"ch\": \"{}\", \"fg\": {}, \"bg\": {}}}", json_string(&cell.ch.to_string()), json_ink(cell.fg), json_ink(cell.bg))
+        }).collect();
+        out.push_str(if y == 0 { "\n[" } else { ",\n[" });
+        out.push_str(&cells.join(", "));
+        out.push(']');
+    }
+
+    out.push_str("]}\n");
+    out
+}
+
+fn json_ink(ink: Ink) -> String {
+    match ink {
+        Ink::Default => "null".to_string(),
+        Ink::Indexed(i) => i.to_string(),
+        Ink::Rgb(r, g, b) => format!("\"{}\"", hex((r, g, b))),
+    }
+}
+
+// Turn a grid into the binary form. Fails if the grid is too big for the
+// sizes to fit.
+pub fn to_binary(grid: &Grid, theme: &Theme, mode: &str) -> Result<Vec<u8>, String> {
+    if grid.width > u16::MAX as u32 || grid.height > u16::MAX as u32 {
+        return Err(format!("The picture is {}x{}, but the cells format can only hold up to {} cells each way.", grid.width, grid.height, u16::MAX));
+    }
+    let mut out = Vec::new();
+    out.extend_from_slice(b"RIVR");
+    out.push(1);
+    out.extend_from_slice(&(grid.width as u16).to_le_bytes());
+    out.extend_from_slice(&(grid.height as u16).to_le_bytes());
+    out.push(mode.len() as u8);
+    out.extend_from_slice(mode.as_bytes());
+
+    out.extend_from_slice(&(theme.palette.len() as u16).to_le_bytes());
+    for &(r, g, b) in theme.palette.iter().chain([theme.fg, theme.bg].iter()) {
+        out.extend_from_slice(&[r, g, b]);
+    }
+
+    for cell in &grid.cells {
+        out.extend_from_slice(&(cell.ch as u32).to_le_bytes());
+        push_ink(&mut out, cell.fg);
+        push_ink(&mut out, cell.bg);
+    }
+    Ok(out)
+}
+
+fn push_ink(out: &mut Vec<u8>, ink: Ink) {
+    match ink {
+        Ink::Default => out.push(0),
+        Ink::Indexed(i) => out.extend_from_slice(&[1, i]),
+        Ink::Rgb(r, g, b) => out.extend_from_slice(&[2, r, g, b]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::Cell;
+
+    fn theme() -> Theme {
+        Theme { palette: vec![(1, 2, 3), (4, 5, 6)], fg: (7, 8, 9), bg: (10, 11, 12) }
+    }
+
+    #[test]
+    fn binary_layout() {
+        let mut grid = Grid::new(2, 1);
+        grid.set(0, 0, Cell { ch: '\u{2580}', fg: Ink::Indexed(1), bg: Ink::Rgb(255, 0, 128) });
+        let data = to_binary(&grid, &theme(), "16colors").unwrap();
+        let mut expected = b"RIVR\x01\x02\x00\x01\x00\x0816colors\x02\x00".to_vec();
+        expected.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        expected.extend_from_slice(&[0x80, 0x25, 0, 0, 1, 1, 2, 255, 0, 128]);
+        expected.extend_from_slice(&[b' ', 0, 0, 0, 0, 0]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn binary_refuses_huge_grids() {
+        assert!(to_binary(&Grid::new(65536, 1), &theme(), "ascii").is_err());
+        assert!(to_binary(&Grid::new(1, 65536), &theme(), "ascii").is_err());
+        assert!(to_binary(&Grid::new(65535, 1), &theme(), "ascii").is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+// HTML output. The picture goes in a <pre> block, with a <span> for every
+// run of cells in the same colors.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use grid::{Grid, Ink};
+use super::{Theme, hex, escape};
+
+// Monospace fonts are about 0.6 of their size wide, so with this line height
+// the characters come out twice as tall as they're wide, like in most
+// terminals.
+const LINE_HEIGHT: &str = "1.2";
+
+// Turn a grid into HTML. Normally this is a whole page, but with fragment
+// set it's just the <pre> block to paste into another page. With classes
+// set the colors are given as CSS classes named after the palette entries
+// (.f1 for red text, .b4 for a blue background and .fxff8800 for a color
+// that isn't in the palette) instead of inline styles, so a page can give
+// them its own colors.
+pub fn to_html(grid: &Grid, theme: &Theme, title: &str, fragment: bool, classes: bool) -> String {
+    let mut body = String::new();
+    let mut used: Vec<(String, String)> = Vec::new();
+
+    for y in 0..grid.height {
+        let mut x = 0;
+        while x < grid.width {
+            // Find the run of cells with the same colors as this one.
+            let first = grid.get(x, y);
+            let mut text = String::new();
+            while x < grid.width {
+                let cell = grid.get(x, y);
+                if cell.fg != first.fg || cell.bg != first.bg {
+                    break;
+                }
+                text.push_str(&escape(cell.ch));
+                x += 1;
+            }
+
+            if first.fg == Ink::Default && first.bg == Ink::Default {
+                body.push_str(&text);
+            } else if classes {
+                let mut names = Vec::new();
+                for &(ink, prefix, property) in [(first.fg, "f", "color"), (first.bg, "b", "background")].iter() {
+                    if let Some(name) = class_name(ink, prefix) {
+                        let color = if prefix == "f" { theme.fg(ink) } else { theme.bg(ink) };
+                        let rule = format!(".{} {{ {}: {}; }}", name, property, hex(color));
+                        if !used.iter().any(|(n, _)| *n == name) {
+                            used.push((name.clone(), rule));
+                        }
+                        names.push(name);
+                    }
+                }
+                body.push_str(&format!("<span class=\"{}\">{}</span>", names.join(" "), text));
+            } else {
+                let mut style = Vec::new();
+                if first.fg != Ink::Default {
+                    style.push(format!("color:{}", hex(theme.fg(first.fg))));
+                }
+                if first.bg != Ink::Default {
+                    style.push(format!("background:{}", hex(theme.bg(first.bg))));
+                }
+                body.push_str(&format!("<span style=\"{}\">{}</span>", style.join(";"), text));
+            }
+        }
+        body.push('\n');
+    }
+
+    let pre_style = format!("font-family: monospace; line-height: {}; color: {}; background: {}; display: inline-block; margin: 0;",
+        LINE_HEIGHT, hex(theme.fg), hex(theme.bg));
+    let rules: Vec<String> = used.into_iter().map(|(_, rule)| rule).collect();
+
+    let mut out = String::new();
+    if fragment {
+        if classes {
+            out.push_str(&format!("<style>\npre.river {{ {} }}\n{}\n</style>\n", pre_style, rules.join("\n")));
+            out.push_str(&format!("<pre class=\"river\">{}</pre>\n", body));
+        } else {
+            out.push_str(&format!("<pre style=\"{}\">{}</pre>\n", pre_style, body));
+        }
+        return out;
+    }
+
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n", title.chars().map(escape).collect::<String>()));
+    out.push_str(&format!("<style>\nbody {{ background: {}; }}\npre.river {{ {} }}\n", hex(theme.bg), pre_style));
+    for rule in rules {
+        out.push_str(&rule);
+        out.push('\n');
+    }
+    out.push_str("</style>\n</head>\n<body>\n");
+    out.push_str(&format!("<pre class=\"river\">{}</pre>\n", body));
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+// The CSS class for a color, or None for the default color.
+fn class_name(ink: Ink, prefix: &str) -> Option<String> {
+    match ink {
+        Ink::Default => None,
+        Ink::Indexed(i) => Some(format!("{}{}", prefix, i)),
+        Ink::Rgb(r, g, b) => Some(format!("{}x{:02x}{:02x}{:02x}", prefix, r, g, b)),
+    }
+}
@@ -0,0 +1,110 @@
+// Writing a rendered grid out in formats other than terminal escape codes,
+// for putting river's pictures on web pages, in documents and so on.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use grid::Ink;
+
+pub mod ans;
+pub mod cast;
+pub mod chat;
+pub mod data;
+pub mod html;
+pub mod png;
+pub mod svg;
+
+// What the terminal's colors look like, which we need to know once the
+// picture isn't in a terminal anymore.
+pub struct Theme {
+    // All 256 palette colors.
+    pub palette: Vec<(u8, u8, u8)>,
+    // The default text and background colors.
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+}
+
+impl Theme {
+    // The real color of some text.
+    pub fn fg(&self, ink: Ink) -> (u8, u8, u8) {
+        self.resolve(ink, self.fg)
+    }
+
+    // The real color of a background.
+    pub fn bg(&self, ink: Ink) -> (u8, u8, u8) {
+        self.resolve(ink, self.bg)
+    }
+
+    fn resolve(&self, ink: Ink, default: (u8, u8, u8)) -> (u8, u8, u8) {
+        match ink {
+            Ink::Default => default,
+            Ink::Indexed(i) => self.palette[i as usize],
+            Ink::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+}
+
+// Find the closest of the first few palette colors to a color. The first 8
+// or 16 are the classic terminal colors, which are all some formats can do.
+pub fn nearest(theme: &Theme, count: usize, color: (u8, u8, u8)) -> usize {
+    let mut best = 0;
+    for i in 1..count {
+        if distance(theme.palette[i], color) < distance(theme.palette[best], color) {
+            best = i;
+        }
+    }
+    best
+}
+
+// How different two colors are, as the squared distance between them.
+pub fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    let (dr, dg, db) = (a.0 as i32 - b.0 as i32, a.1 as i32 - b.1 as i32, a.2 as i32 - b.2 as i32);
+    dr*dr + dg*dg + db*db
+}
+
+// Write a color the way HTML and SVG want it, as #rrggbb.
+pub fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+// Escape the characters that mean something in HTML and XML.
+pub fn escape(c: char) -> String {
+    match c {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        _ => c.to_string(),
+    }
+}
+
+// Escape text to go between the quotes of a JSON string.
+pub fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
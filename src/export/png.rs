@@ -0,0 +1,151 @@
+// PNG output: a picture of what the rendering looks like in a terminal,
+// drawn with the built-in font. Handy for sharing, and for checking that a
+// change to river doesn't change its output without needing a terminal.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io;
+use image::{self, ColorType, RgbImage};
+use font;
+use grid::Grid;
+use super::Theme;
+
+// Draw a grid into an image, one font-sized block of pixels per cell.
+// Characters the font doesn't have are drawn as question marks.
+pub fn rasterize(grid: &Grid, theme: &Theme) -> RgbImage {
+    let mut img = RgbImage::new(grid.width*font::WIDTH, grid.height*font::HEIGHT);
+    let fallback = font::glyph('?').unwrap();
+
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let cell = grid.get(x, y);
+            let (fr, fg, fb) = theme.fg(cell.fg);
+            let (br, bg, bb) = theme.bg(cell.bg);
+            let rows = font::glyph(cell.ch).unwrap_or(fallback);
+
+            for (dy, row) in rows.iter().enumerate() {
+                for dx in 0..font::WIDTH {
+                    let ink = row & (0x80 >> dx) != 0;
+                    let color = if ink { [fr, fg, fb] } else { [br, bg, bb] };
+                    img.put_pixel(x*font::WIDTH + dx, y*font::HEIGHT + dy as u32, image::Rgb { data: color });
+                }
+            }
+        }
+    }
+
+    img
+}
+
+// Draw a grid and encode it as a PNG file. The encoder can still refuse,
+// say if the picture is too big for it.
+pub fn to_png(grid: &Grid, theme: &Theme) -> io::Result<Vec<u8>> {
+    let img = rasterize(grid, theme);
+    let (width, height) = img.dimensions();
+    let mut out = Vec::new();
+    image::png::PNGEncoder::new(&mut out).encode(&img.into_raw(), width, height, ColorType::RGB(8))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::{Cell, Ink};
+
+    fn theme() -> Theme {
+        Theme {
+            palette: (0..256).map(|i| (i as u8, 0, 255 - i as u8)).collect(),
+            fg: (200, 200, 200),
+            bg: (0, 0, 0),
+        }
+    }
+
+    // Draw the pixels of an image as text, # for the color ink and . for
+    // anything else, so a picture can be checked against one typed out.
+    fn picture(img: &RgbImage, ink: (u8, u8, u8)) -> Vec<String> {
+        (0..img.height())
+            .map(|y| (0..img.width())
+                .map(|x| if img.get_pixel(x, y).data == [ink.0, ink.1, ink.2] { '#' } else { '.' })
+                .collect())
+            .collect()
+    }
+
+    #[test]
+    fn ascii_cells_are_drawn_with_the_font() {
+        let mut grid = Grid::new(2, 1);
+        grid.set(0, 0, Cell::glyph('W'));
+        let img = rasterize(&grid, &theme());
+        assert_eq!(img.dimensions(), (16, 13));
+        assert_eq!(picture(&img, (200, 200, 200)), vec![
+            "................",
+            "................",
+            "#.....#.........",
+            "#.....#.........",
+            "#.....#.........",
+            "#.....#.........",
+            "#..#..#.........",
+            "#..#..#.........",
+            "#..#..#.........",
+            "#.#.#.#.........",
+            ".#...#..........",
+            "................",
+            "................",
+        ]);
+        // Everything else is the background.
+        assert!(img.pixels().all(|p| p.data == [200, 200, 200] || p.data == [0, 0, 0]));
+    }
+
+    #[test]
+    fn block_cells_use_both_colors() {
+        let mut grid = Grid::new(1, 1);
+        grid.set(0, 0, Cell { ch: '\u{2580}', fg: Ink::Indexed(9), bg: Ink::Rgb(1, 2, 3) });
+        let img = rasterize(&grid, &theme());
+        for (x, y, p) in img.enumerate_pixels() {
+            let expected = if y < 6 { [9, 0, 246] } else { [1, 2, 3] };
+            assert_eq!(p.data, expected, "pixel {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn shaded_cells_are_a_checkerboard() {
+        let mut grid = Grid::new(1, 1);
+        grid.set(0, 0, Cell { ch: '\u{2592}', fg: Ink::Rgb(255, 255, 255), bg: Ink::Default });
+        let img = rasterize(&grid, &theme());
+        let rows = picture(&img, (255, 255, 255));
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(row, if y % 2 == 0 { "#.#.#.#." } else { ".#.#.#.#" });
+        }
+    }
+
+    #[test]
+    fn missing_characters_are_question_marks() {
+        let mut grid = Grid::new(2, 1);
+        grid.set(0, 0, Cell::glyph('\u{4e00}'));
+        grid.set(1, 0, Cell::glyph('?'));
+        let img = rasterize(&grid, &theme());
+        for (x, y, p) in img.enumerate_pixels().filter(|&(x, _, _)| x < 8) {
+            assert_eq!(p, img.get_pixel(x + 8, y));
+        }
+    }
+
+    #[test]
+    fn png_decodes_to_the_same_pixels() {
+        let mut grid = Grid::new(3, 2);
+        grid.set(1, 0, Cell { ch: '#', fg: Ink::Indexed(200), bg: Ink::Rgb(10, 20, 30) });
+        grid.set(2, 1, Cell::block(Ink::Indexed(3)));
+        let png = to_png(&grid, &theme()).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgb();
+        assert_eq!(decoded.into_raw(), rasterize(&grid, &theme()).into_raw());
+    }
+}
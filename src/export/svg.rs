@@ -0,0 +1,121 @@
+// SVG output. Every cell is a colored rectangle with a character on top,
+// but neighboring cells in the same color share one rectangle or one piece
+// of text, so big flat areas don't turn into thousands of elements.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use grid::{Grid, Ink};
+use super::{Theme, hex, escape};
+
+// The size of a cell, twice as tall as it's wide like in most terminals.
+const CELL_WIDTH: u32 = 8;
+const CELL_HEIGHT: u32 = 16;
+// Monospace fonts are about 0.6 of their size wide, so this fills the cell.
+const FONT_SIZE: &str = "13.33";
+// Where the baseline of the text goes, from the top of the cell.
+const BASELINE: u32 = 12;
+
+// A rectangle of background color, in cells.
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    color: (u8, u8, u8),
+}
+
+// Turn a grid into an SVG picture.
+pub fn to_svg(grid: &Grid, theme: &Theme) -> String {
+    let (width, height) = (grid.width*CELL_WIDTH, grid.height*CELL_HEIGHT);
+    let mut out = String::new();
+    let _ = writeln!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"monospace\" font-size=\"{}\" xml:space=\"preserve\">",
+        width, height, width, height, FONT_SIZE);
+    let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>", hex(theme.bg));
+
+    for rect in background_rects(grid, theme) {
+        let _ = writeln!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+            rect.x*CELL_WIDTH, rect.y*CELL_HEIGHT, rect.width*CELL_WIDTH, rect.height*CELL_HEIGHT, hex(rect.color));
+    }
+
+    // The text goes on top in runs of the same color. Runs of nothing but
+    // spaces are left out, and the text is stretched to exactly the width of
+    // its cells so it lines up whatever font the viewer picks.
+    for y in 0..grid.height {
+        let mut x = 0;
+        while x < grid.width {
+            let fg = grid.get(x, y).fg;
+            let start = x;
+            let mut text = String::new();
+            while x < grid.width && grid.get(x, y).fg == fg {
+                text.push(grid.get(x, y).ch);
+                x += 1;
+            }
+            let trimmed = text.trim_end();
+            let leading = trimmed.len() - trimmed.trim_start().len();
+            let run = trimmed.trim_start();
+            if run.is_empty() {
+                continue;
+            }
+            let first = start + text[..leading].chars().count() as u32;
+            let length = run.chars().count() as u32;
+            let _ = writeln!(out, "<text x=\"{}\" y=\"{}\" fill=\"{}\" textLength=\"{}\" lengthAdjust=\"spacingAndGlyphs\">{}</text>",
+                first*CELL_WIDTH, y*CELL_HEIGHT + BASELINE, hex(theme.fg(fg)), length*CELL_WIDTH,
+                run.chars().map(escape).collect::<String>());
+        }
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+// Find the rectangles of background color. Each row is split into runs of
+// one color, and a run that lines up exactly with one in the row above makes
+// that rectangle taller instead of starting a new one. The default
+// background is already covered by the first rectangle.
+fn background_rects(grid: &Grid, theme: &Theme) -> Vec<Rect> {
+    let mut done = Vec::new();
+    let mut open: Vec<Rect> = Vec::new();
+
+    for y in 0..grid.height {
+        let mut still_open = Vec::new();
+        let mut x = 0;
+        while x < grid.width {
+            let bg = grid.get(x, y).bg;
+            let start = x;
+            while x < grid.width && grid.get(x, y).bg == bg {
+                x += 1;
+            }
+            if bg == Ink::Default {
+                continue;
+            }
+            let color = theme.bg(bg);
+
+            match open.iter().position(|r| r.x == start && r.width == x - start && r.color == color) {
+                Some(i) => {
+                    let mut rect = open.swap_remove(i);
+                    rect.height += 1;
+                    still_open.push(rect);
+                },
+                None => still_open.push(Rect { x: start, y, width: x - start, height: 1, color }),
+            }
+        }
+        // Whatever wasn't continued on this row is finished.
+        done.append(&mut open);
+        open = still_open;
+    }
+    done.append(&mut open);
+    done
+}
@@ -0,0 +1,141 @@
+// The grid of character cells that the render modes draw into. Keeping the
+// picture as characters plus colors, instead of printing it straight away,
+// means one rendering can be turned into terminal escape codes or anything
+// else that can show colored text.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt::Write;
+use termion::color;
+
+// The color of the text or the background of a cell.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Ink {
+    // Whatever color the terminal uses by default.
+    Default,
+    // One of the terminal's 256 palette colors.
+    Indexed(u8),
+    // A 24-bit color.
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Ink,
+    pub bg: Ink,
+}
+
+impl Cell {
+    // A space with the terminal's default colors.
+    pub fn blank() -> Cell {
+        Cell { ch: ' ', fg: Ink::Default, bg: Ink::Default }
+    }
+
+    // A character in the terminal's default colors.
+    pub fn glyph(ch: char) -> Cell {
+        Cell { ch, fg: Ink::Default, bg: Ink::Default }
+    }
+
+    // A space with a colored background, i.e. one big colored pixel.
+    pub fn block(bg: Ink) -> Cell {
+        Cell { ch: ' ', fg: Ink::Default, bg }
+    }
+}
+
+pub struct Grid {
+    pub width: u32,
+    pub height: u32,
+    // The cells, row by row from the top left.
+    pub cells: Vec<Cell>,
+}
+
+impl Grid {
+    // Make a grid full of blank cells.
+    pub fn new(width: u32, height: u32) -> Grid {
+        Grid { width, height, cells: vec![Cell::blank(); (width*height) as usize] }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Cell {
+        self.cells[(self.width*y + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, cell: Cell) {
+        self.cells[(self.width*y + x) as usize] = cell;
+    }
+
+    // Make a copy of the grid moved to the right by some blank columns.
+    pub fn indent(&self, columns: u32) -> Grid {
+        let mut grid = Grid::new(self.width + columns, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                grid.set(x + columns, y, self.get(x, y));
+            }
+        }
+        grid
+    }
+}
+
+// Turn a grid into text for the terminal. Escape codes are only written when
+// the color changes, so long runs of one color stay short.
+pub fn to_ansi(grid: &Grid) -> String {
+    let mut out = String::new();
+
+    for y in 0..grid.height {
+        let mut fg = Ink::Default;
+        let mut bg = Ink::Default;
+        for x in 0..grid.width {
+            let cell = grid.get(x, y);
+            if cell.fg != fg {
+                write_fg(&mut out, cell.fg);
+                fg = cell.fg;
+            }
+            if cell.bg != bg {
+                write_bg(&mut out, cell.bg);
+                bg = cell.bg;
+            }
+            out.push(cell.ch);
+        }
+        // Reset colors at the end of each line. If we don't do this, the
+        // color of the rightmost pixel in each line is extended to the right
+        // edge of the screen.
+        if fg != Ink::Default {
+            write_fg(&mut out, Ink::Default);
+        }
+        if bg != Ink::Default {
+            write_bg(&mut out, Ink::Default);
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+fn write_fg(out: &mut String, ink: Ink) {
+    // Writing to a String can't fail.
+    let _ = match ink {
+        Ink::Default => write!(out, "{}", color::Fg(color::Reset)),
+        Ink::Indexed(i) => write!(out, "{}", color::Fg(color::AnsiValue(i))),
+        Ink::Rgb(r, g, b) => write!(out, "{}", color::Fg(color::Rgb(r, g, b))),
+    };
+}
+
+fn write_bg(out: &mut String, ink: Ink) {
+    let _ = match ink {
+        Ink::Default => write!(out, "{}", color::Bg(color::Reset)),
+        Ink::Indexed(i) => write!(out, "{}", color::Bg(color::AnsiValue(i))),
+        Ink::Rgb(r, g, b) => write!(out, "{}", color::Bg(color::Rgb(r, g, b))),
+    };
+}
@@ -0,0 +1,453 @@
+// Reading ANSI art back into a grid, the other way around from everything
+// else river does. This understands the escape codes that river writes and
+// the ones that ANSI art from BBSes and art editors uses: colors, moving the
+// cursor around and clearing the screen, in UTF-8 or in code page 437.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::str;
+use export::ans::CP437_HIGH;
+use grid::{Cell, Grid, Ink};
+
+// The biggest picture we'll read. Real ANSI art is 80 or 160 columns wide
+// and rarely more than a few hundred rows long, so anything bigger is a
+// broken file, and turning it into a picture would run out of memory.
+const MAX_COLUMNS: u32 = 512;
+const MAX_ROWS: u32 = 4000;
+
+// An imaginary terminal that the art is "printed" on.
+struct Screen {
+    rows: Vec<Vec<Cell>>,
+    // Where to wrap, or None to never wrap.
+    width: Option<u32>,
+    x: u32,
+    y: u32,
+    saved: (u32, u32),
+    fg: Ink,
+    bg: Ink,
+    bold: bool,
+    blink: bool,
+    reverse: bool,
+}
+
+impl Screen {
+    // Print a character and move the cursor along. Like real terminals, we
+    // only wrap when the next character comes, so a full-width line followed
+    // by a line break doesn't leave a blank line.
+    fn put(&mut self, ch: char) {
+        if let Some(width) = self.width {
+            if self.x >= width {
+                self.x = 0;
+                self.y = self.y.saturating_add(1);
+            }
+        }
+        while self.rows.len() <= self.y as usize {
+            self.rows.push(Vec::new());
+        }
+        let row = &mut self.rows[self.y as usize];
+        if row.len() <= self.x as usize {
+            row.resize(self.x as usize + 1, Cell::blank());
+        }
+
+        // Bold text and blinking backgrounds are how the classic 16 colors
+        // get their bright halves.
+        let mut fg = match self.fg {
+            Ink::Default if self.bold => Ink::Indexed(15),
+            Ink::Indexed(i) if self.bold && i < 8 => Ink::Indexed(i + 8),
+            ink => ink,
+        };
+        let mut bg = match self.bg {
+            Ink::Default if self.blink => Ink::Indexed(8),
+            Ink::Indexed(i) if self.blink && i < 8 => Ink::Indexed(i + 8),
+            ink => ink,
+        };
+        if self.reverse {
+            fg = if fg == Ink::Default { Ink::Indexed(7) } else { fg };
+            bg = if bg == Ink::Default { Ink::Indexed(0) } else { bg };
+            ::std::mem::swap(&mut fg, &mut bg);
+        }
+        row[self.x as usize] = Cell { ch, fg, bg };
+        self.x = self.x.saturating_add(1);
+    }
+
+    // Handle a control sequence: ESC [, some numbers, and a letter saying
+    // what to do with them.
+    fn control(&mut self, params: &[u32], private: bool, command: char) {
+        if private {
+            // Things like hiding the cursor, which don't change the picture.
+            return;
+        }
+        let n = params.first().cloned().unwrap_or(0).max(1);
+        match command {
+            'm' => self.set_style(params),
+            'A' => self.y = self.y.saturating_sub(n),
+            'B' => self.y = self.y.saturating_add(n),
+            'C' => {
+                self.x = self.x.saturating_add(n);
+                if let Some(width) = self.width {
+                    self.x = self.x.min(width - 1);
+                }
+            },
+            'D' => self.x = self.x.saturating_sub(n),
+            'H' | 'f' => {
+                self.y = params.first().cloned().unwrap_or(1).max(1) - 1;
+                self.x = params.get(1).cloned().unwrap_or(1).max(1) - 1;
+            },
+            'J' if params.first() == Some(&2) => {
+                self.rows.clear();
+                self.x = 0;
+                self.y = 0;
+            },
+            'K' => {
+                if let Some(row) = self.rows.get_mut(self.y as usize) {
+                    row.truncate(self.x as usize);
+                }
+            },
+            's' => self.saved = (self.x, self.y),
+            'u' => {
+                let (x, y) = self.saved;
+                self.x = x;
+                self.y = y;
+            },
+            _ => {},
+        }
+    }
+
+    // Change the colors with an SGR sequence.
+    fn set_style(&mut self, params: &[u32]) {
+        if params.is_empty() {
+            self.set_style(&[0]);
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => {
+                    self.fg = Ink::Default;
+                    self.bg = Ink::Default;
+                    self.bold = false;
+                    self.blink = false;
+                    self.reverse = false;
+                },
+                1 => self.bold = true,
+                5 | 6 => self.blink = true,
+                7 => self.reverse = true,
+                22 => self.bold = false,
+                25 => self.blink = false,
+                27 => self.reverse = false,
+                p @ 30..=37 => self.fg = Ink::Indexed((p - 30) as u8),
+                39 => self.fg = Ink::Default,
+                p @ 40..=47 => self.bg = Ink::Indexed((p - 40) as u8),
+                49 => self.bg = Ink::Default,
+                p @ 90..=97 => self.fg = Ink::Indexed((p - 90 + 8) as u8),
+                p @ 100..=107 => self.bg = Ink::Indexed((p - 100 + 8) as u8),
+                p @ 38 | p @ 48 => {
+                    let (ink, used) = extended_color(&params[i + 1..]);
+                    if let Some(ink) = ink {
+                        if p == 38 { self.fg = ink; } else { self.bg = ink; }
+                    }
+                    i += used;
+                },
+                _ => {},
+            }
+            i += 1;
+        }
+    }
+}
+
+// Read the color after a 38 or 48, which is either 5 and a palette number
+// or 2 and red, green and blue. Also says how many numbers it used.
+fn extended_color(params: &[u32]) -> (Option<Ink>, usize) {
+    match params.first() {
+        Some(&5) if params.len() >= 2 => (Some(Ink::Indexed(params[1].min(255) as u8)), 2),
+        Some(&2) if params.len() >= 4 => {
+            let c = |v: u32| v.min(255) as u8;
+            (Some(Ink::Rgb(c(params[1]), c(params[2]), c(params[3]))), 4)
+        },
+        _ => (None, params.len()),
+    }
+}
+
+// Find the SAUCE record on the end of a file, if it has one.
+fn sauce(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 128 || &data[data.len() - 128..data.len() - 121] != b"SAUCE00" {
+        return None;
+    }
+    Some(&data[data.len() - 128..])
+}
+
+// The width that a SAUCE record on the end of a file says the art is.
+pub fn sauce_width(data: &[u8]) -> Option<u32> {
+    // Only character art (data type 1) has its width there.
+    match sauce(data) {
+        Some(record) if record[94] == 1 => {
+            let width = record[96] as u32 | (record[97] as u32) << 8;
+            if width > 0 { Some(width) } else { None }
+        },
+        _ => None,
+    }
+}
+
+// Turn ANSI art into a grid, wrapping lines at width if there is one. The
+// file can be UTF-8, like river's own output, or code page 437 like most
+// ANSI art. A bit of code page 437 can happen to be valid UTF-8 too, so
+// files with a SAUCE record, which come from ANSI art editors, are always
+// taken to be code page 437. Fails if the art goes further than
+// MAX_COLUMNS or MAX_ROWS.
+pub fn parse_ansi(data: &[u8], width: Option<u32>) -> Result<Grid, String> {
+    if width.unwrap_or(0) > MAX_COLUMNS {
+        return Err(format!("River can only read ANSI art up to {} columns wide.", MAX_COLUMNS));
+    }
+    let cp437 = sauce(data).is_some();
+    // Everything after an end of file character is a SAUCE record or other
+    // things that aren't meant to be shown.
+    let data = match data.iter().position(|&b| b == 0x1a) {
+        Some(end) => &data[..end],
+        None => data,
+    };
+    let text: Vec<char> = match str::from_utf8(data) {
+        Ok(s) if !cp437 => s.chars().collect(),
+        _ => data.iter().map(|&b| if b < 0x80 { b as char } else { CP437_HIGH[b as usize - 0x80] }).collect(),
+    };
+
+    let mut screen = Screen {
+        rows: Vec::new(),
+        width: width.filter(|&w| w > 0),
+        x: 0,
+        y: 0,
+        saved: (0, 0),
+        fg: Ink::Default,
+        bg: Ink::Default,
+        bold: false,
+        blink: false,
+        reverse: false,
+    };
+
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i];
+        i += 1;
+        match c {
+            '\x1b' if i < text.len() && text[i] == '[' => {
+                i += 1;
+                let private = i < text.len() && text[i] == '?';
+                if private {
+                    i += 1;
+                }
+                let mut params = Vec::new();
+                let mut number: Option<u32> = None;
+                while i < text.len() {
+                    let c = text[i];
+                    i += 1;
+                    if let Some(d) = c.to_digit(10) {
+                        number = Some(number.unwrap_or(0).saturating_mul(10).saturating_add(d));
+                    } else if c == ';' {
+                        params.push(number.take().unwrap_or(0));
+                    } else if ('@'..='~').contains(&c) {
+                        if let Some(n) = number {
+                            params.push(n);
+                        }
+                        screen.control(&params, private, c);
+                        break;
+                    }
+                }
+            },
+            '\x1b' if i < text.len() && text[i] == ']' => {
+                // Operating system commands, like setting the window title,
+                // run until a bell or ESC \.
+                while i < text.len() && text[i] != '\x07' && text[i] != '\x1b' {
+                    i += 1;
+                }
+                i += if i < text.len() && text[i] == '\x1b' { 2 } else { 1 };
+            },
+            // Picking a character set, like ESC ( B, has one more character.
+            '\x1b' if i < text.len() && "()*+".contains(text[i]) => i += 2,
+            '\x1b' => i += 1,
+            '\r' => screen.x = 0,
+            '\n' => {
+                screen.x = 0;
+                screen.y = screen.y.saturating_add(1);
+            },
+            '\t' => {
+                screen.x = (screen.x / 8).saturating_add(1).saturating_mul(8);
+                if let Some(width) = screen.width {
+                    screen.x = screen.x.min(width - 1);
+                }
+            },
+            '\x08' => screen.x = screen.x.saturating_sub(1),
+            c if c < ' ' || c == '\x7f' => {},
+            c => screen.put(c),
+        }
+
+        // Check where the cursor is after everything, before it can be used
+        // to make a row or a cell.
+        if screen.x > MAX_COLUMNS || screen.y >= MAX_ROWS {
+            return Err(format!("The ANSI art goes past {} columns or {} rows, which is more than river can read.", MAX_COLUMNS, MAX_ROWS));
+        }
+    }
+
+    let grid_width = screen.width.unwrap_or_else(|| screen.rows.iter().map(|r| r.len() as u32).max().unwrap_or(0));
+    let mut grid = Grid::new(grid_width, screen.rows.len() as u32);
+    for (y, row) in screen.rows.iter().enumerate() {
+        for (x, &cell) in row.iter().enumerate().take(grid_width as usize) {
+            grid.set(x as u32, y as u32, cell);
+        }
+    }
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use export::{self, Theme};
+    use grid;
+
+    // The characters of a grid, row by row.
+    fn text(grid: &Grid) -> Vec<String> {
+        (0..grid.height).map(|y| (0..grid.width).map(|x| grid.get(x, y).ch).collect()).collect()
+    }
+
+    fn parse(data: &str, width: Option<u32>) -> Grid {
+        parse_ansi(data.as_bytes(), width).unwrap()
+    }
+
+    // A grid with some of every kind of cell in it.
+    fn sample() -> Grid {
+        let mut grid = Grid::new(5, 3);
+        let chars = ['A', '\u{2588}', '\u{2591}', '\u{2580}', 'z'];
+        for y in 0..3 {
+            for x in 0..5 {
+                let i = (y*5 + x) as u8;
+                grid.set(x, y, Cell { ch: chars[x as usize], fg: Ink::Indexed(i % 16), bg: Ink::Indexed((i*7 + 3) % 16) });
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn moves_the_cursor() {
+        assert_eq!(text(&parse("abc\x1b[2Dx", None)), vec!["axc"]);
+        assert_eq!(text(&parse("\x1b[2;3Hx", None)), vec!["   ", "  x"]);
+        assert_eq!(text(&parse("ab\ncd\x1b[Ae", None)), vec!["abe", "cd "]);
+        assert_eq!(text(&parse("a\x1b[2Bb", None)), vec!["a ", "  ", " b"]);
+        assert_eq!(text(&parse("a\x1b[sbc\x1b[ux", None)), vec!["axc"]);
+        assert_eq!(text(&parse("a\tb", None)), vec!["a       b"]);
+        assert_eq!(text(&parse("old\x1b[2Jnew", None)), vec!["new"]);
+        assert_eq!(text(&parse("abcd\r\x1b[2C\x1b[K", None)), vec!["ab"]);
+        // Moving right stops at the edge when there is one.
+        assert_eq!(text(&parse("\x1b[99Cx", Some(4))), vec!["   x"]);
+    }
+
+    #[test]
+    fn wraps_at_the_width() {
+        assert_eq!(text(&parse("abcde", Some(2))), vec!["ab", "cd", "e "]);
+        // A full line and then a line break is still only one line.
+        assert_eq!(text(&parse("ab\r\ncd", Some(2))), vec!["ab", "cd"]);
+    }
+
+    #[test]
+    fn sets_colors() {
+        let grid = parse("\x1b[31;42ma\x1b[1mb\x1b[0;5mc\x1b[7md\x1b[0;38;5;200;48;2;1;2;3me\x1b[0;95;104mf\x1b[39;49mg", None);
+        let inks: Vec<(Ink, Ink)> = grid.cells.iter().map(|c| (c.fg, c.bg)).collect();
+        assert_eq!(inks, vec![
+            (Ink::Indexed(1), Ink::Indexed(2)),
+            (Ink::Indexed(9), Ink::Indexed(2)),
+            (Ink::Default, Ink::Indexed(8)),
+            (Ink::Indexed(8), Ink::Indexed(7)),
+            (Ink::Indexed(200), Ink::Rgb(1, 2, 3)),
+            (Ink::Indexed(13), Ink::Indexed(12)),
+            (Ink::Default, Ink::Default),
+        ]);
+    }
+
+    #[test]
+    fn skips_other_escape_codes() {
+        assert_eq!(text(&parse("\x1b[?25la\x1b]0;title\x07b\x1b]0;title\x1b\\c\x1b(Bd", None)), vec!["abcd"]);
+    }
+
+    #[test]
+    fn reads_code_page_437() {
+        // Not valid UTF-8, so it has to be code page 437.
+        assert_eq!(text(&parse_ansi(&[0xb0, 0xdb, b'a'], None).unwrap()), vec!["\u{2591}\u{2588}a"]);
+        // Valid UTF-8 stays UTF-8...
+        assert_eq!(text(&parse("\u{e9}\u{2580}", None)), vec!["\u{e9}\u{2580}"]);
+        // ...unless there's a SAUCE record, which only ANSI art editors add.
+        let mut data = "\u{e9}".as_bytes().to_vec();
+        data.push(0x1a);
+        let mut record = b"SAUCE00".to_vec();
+        record.resize(128, 0);
+        data.extend_from_slice(&record);
+        assert_eq!(text(&parse_ansi(&data, None).unwrap()), vec!["\u{251c}\u{2310}"]);
+    }
+
+    #[test]
+    fn finds_the_sauce_width() {
+        let theme = test_theme();
+        let sauce = export::ans::Sauce { title: "t", author: "a" };
+        let data = export::ans::to_ans(&sample(), &theme, false, Some(&sauce));
+        assert_eq!(sauce_width(&data), Some(5));
+        assert_eq!(sauce_width(&export::ans::to_ans(&sample(), &theme, false, None)), None);
+        assert_eq!(sauce_width(b"too short"), None);
+    }
+
+    #[test]
+    fn refuses_art_thats_too_big() {
+        assert!(parse_ansi(b"x", Some(MAX_COLUMNS + 1)).is_err());
+        assert!(parse_ansi(b"\x1b[9999Cx", None).is_err());
+        assert!(parse_ansi(b"\x1b[4294967295Bx", None).is_err());
+        assert!(parse_ansi(b"\x1b[99999;1Hx", None).is_err());
+        assert!(parse_ansi("\n".repeat(MAX_ROWS as usize).as_bytes(), None).is_err());
+        assert!(parse_ansi("x".repeat(MAX_COLUMNS as usize + 1).as_bytes(), None).is_err());
+        assert!(parse_ansi("x".repeat(MAX_COLUMNS as usize).as_bytes(), None).is_ok());
+    }
+
+    // 16 colors that are all different, so every palette color is its own
+    // nearest match.
+    fn test_theme() -> Theme {
+        let mut palette: Vec<(u8, u8, u8)> = (0..16).map(|i| (i*16, 255 - i*16, i*8)).collect();
+        palette.resize(256, (0, 0, 0));
+        Theme { palette, fg: (240, 15, 120), bg: (0, 255, 0) }
+    }
+
+    #[test]
+    fn reads_back_ans_files() {
+        let grid = sample();
+        let theme = test_theme();
+        let sauce = export::ans::Sauce { title: "t", author: "a" };
+        let read = parse_ansi(&export::ans::to_ans(&grid, &theme, false, Some(&sauce)), Some(5)).unwrap();
+        assert_eq!((read.width, read.height), (5, 3));
+        assert!(read.cells == grid.cells);
+
+        // Wrapped files are padded out to 80 columns.
+        let read = parse_ansi(&export::ans::to_ans(&grid, &theme, true, None), Some(80)).unwrap();
+        assert_eq!((read.width, read.height), (80, 3));
+        for y in 0..3 {
+            for x in 0..5 {
+                assert_eq!(read.get(x, y), grid.get(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_back_terminal_output() {
+        let mut grid = sample();
+        grid.set(1, 1, Cell { ch: '#', fg: Ink::Rgb(1, 2, 3), bg: Ink::Indexed(200) });
+        grid.set(2, 2, Cell::blank());
+        grid.set(3, 0, Cell::glyph('\u{e9}'));
+        let read = parse(&grid::to_ansi(&grid), None);
+        assert_eq!((read.width, read.height), (5, 3));
+        assert!(read.cells == grid.cells);
+    }
+}
@@ -15,17 +15,37 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
 use std::process;
+use std::collections::HashSet;
 use std::f32;
+use std::iter;
 extern crate image;
-use image::{GenericImage, FilterType};
+use image::{GenericImage, FilterType, ImageBuffer, Rgba};
 extern crate termion;
-use termion::color;
 extern crate clap;
 use clap::{App, Arg};
 extern crate exoquant;
 use exoquant::*;
+use exoquant::optimizer::Optimizer;
+extern crate libc;
+extern crate gif;
+
+mod adjust;
+mod animation;
+mod charset;
+mod edges;
+mod export;
+mod font;
+mod grid;
+mod import;
+mod palette;
+mod sharpen;
+mod term;
+mod transform;
+mod video;
 
 fn main() {
     // Parse command line input.
@@ -34,14 +54,73 @@ fn main() {
         .about("Print images in the Terminal using text characters.")
         .author("Thomas Szymczak")
         .arg(Arg::with_name("INPUT")
-            .help("The name of the input file")
-            .required(true)
+            .help("The name of the input file, or - to play YUV4MPEG2 video from stdin")
+            .required_unless_one(&["from-ansi", "raw", "sequence"])
             .index(1))
         .arg(Arg::with_name("mode")
             .help("What visual style to use when printing the image.")
             .short("m")
             .takes_value(true)
-            .possible_values(&["pound", "ascii", "ascii-simple", "8colors", "16colors", "256colors", "truecolor"]))
+            .possible_values(&["pound", "ascii", "ascii-simple", "edges", "8colors", "16colors", "8colors-shaded", "16colors-shaded", "256colors", "truecolor"]))
+        .arg(Arg::with_name("format")
+            .help("What to write the picture as: escape codes for the terminal (ansi), an ANSI art file for BBS art editors (ans), a web page (html), a vector image (svg), a picture of it drawn with river's built-in font (png), text to paste into IRC (irc), forums (bbcode) or Discord (discord), or the grid of characters and colors for other programs to read, as JSON (json) or in binary (cells).")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["ansi", "ans", "html", "svg", "png", "irc", "bbcode", "discord", "json", "cells"]))
+        .arg(Arg::with_name("output")
+            .help("Write the picture to this file instead of the terminal.")
+            .short("o")
+            .long("output")
+            .takes_value(true))
+        .arg(Arg::with_name("fragment")
+            .help("With --format html, only write the <pre> block to put in another page.")
+            .long("fragment"))
+        .arg(Arg::with_name("css-classes")
+            .help("With --format html, give the colors as CSS classes named after the palette entries instead of inline styles.")
+            .long("css-classes"))
+        .arg(Arg::with_name("irc-colors")
+            .help("With --format irc, use only the 16 basic mIRC colors or all 99.")
+            .long("irc-colors")
+            .takes_value(true)
+            .possible_values(&["16", "99"]))
+        .arg(Arg::with_name("wrap-80")
+            .help("With --format ans, make every line exactly 80 columns with no line breaks, the way ANSI art viewers expect. The picture is made at most 80 columns wide to fit.")
+            .long("wrap-80"))
+        .arg(Arg::with_name("sauce")
+            .help("With --format ans, add a SAUCE record saying who made the picture and how to show it.")
+            .long("sauce"))
+        .arg(Arg::with_name("title")
+            .help("The title for the SAUCE record. The default is the name of the input file.")
+            .long("title")
+            .takes_value(true))
+        .arg(Arg::with_name("author")
+            .help("The author for the SAUCE record.")
+            .long("author")
+            .takes_value(true))
+        .arg(Arg::with_name("from-ansi")
+            .help("Turn ANSI art from this file back into a picture, written with --format (png unless you say otherwise).")
+            .long("from-ansi")
+            .takes_value(true))
+        .arg(Arg::with_name("ansi-width")
+            .help("With --from-ansi, wrap lines at this many columns, or 0 not to wrap them. The default is the width in the SAUCE record, or 80.")
+            .long("ansi-width")
+            .takes_value(true))
+        .arg(Arg::with_name("raw")
+            .help("Play raw RGB video of this size (like 640x360) from stdin.")
+            .long("raw")
+            .takes_value(true))
+        .arg(Arg::with_name("sequence")
+            .help("Play the images in this directory one after another, like a video.")
+            .long("sequence")
+            .takes_value(true))
+        .arg(Arg::with_name("fps")
+            .help("How many frames a second to play raw video or an image sequence at.")
+            .long("fps")
+            .takes_value(true))
+        .arg(Arg::with_name("record")
+            .help("Also write an animation to this file as an asciinema recording.")
+            .long("record")
+            .takes_value(true))
         .arg(Arg::with_name("height")
             .help("Manually set the height of the terminal in columns.")
             .short("y")
@@ -51,21 +130,159 @@ fn main() {
             .short("x")
             .takes_value(true))
         .arg(Arg::with_name("ratio")
-            .help("Set the aspect ratio (width divided by height) of the terminal's characters.")
+            .help("Set the aspect ratio (width divided by height) of the terminal's characters. By default this is worked out from the terminal's size in pixels.")
             .short("r")
             .takes_value(true))
+        .arg(Arg::with_name("crop")
+            .help("Only show part of the image, given as x,y,width,height in pixels or in percent, e.g. 50%,0,50%,100% for the right half.")
+            .long("crop")
+            .takes_value(true))
+        .arg(Arg::with_name("rotate")
+            .help("Turn the image clockwise by this many degrees.")
+            .long("rotate")
+            .takes_value(true)
+            .possible_values(&["90", "180", "270"]))
+        .arg(Arg::with_name("flip")
+            .help("Mirror the image horizontally (h) or vertically (v).")
+            .long("flip")
+            .takes_value(true)
+            .possible_values(&["h", "v"]))
+        .arg(Arg::with_name("no-exif")
+            .help("Don't turn photos the right way up using the orientation saved by the camera.")
+            .long("no-exif"))
+        .arg(Arg::with_name("scale")
+            .help("How to fit the image to the terminal: fit inside it, fill it and crop the rest, stretch to its shape, match only its width (for piping tall images to a pager) or height, or none for one character per pixel, leaving the image as it is.")
+            .long("scale")
+            .takes_value(true)
+            .possible_values(&["fit", "fill", "stretch", "none", "width", "height"]))
+        .arg(Arg::with_name("align")
+            .help("Where to put the image when it's narrower than the terminal.")
+            .long("align")
+            .takes_value(true)
+            .possible_values(&["left", "center", "right"]))
+        .arg(Arg::with_name("max-upscale")
+            .help("Don't enlarge the image more than this many times, so small icons stay small. 1 means one row per pixel.")
+            .long("max-upscale")
+            .takes_value(true))
+        .arg(Arg::with_name("sharpen")
+            .help("Sharpen the image after shrinking it, so small details don't disappear: unsharp for fine detail, edge for outlines, local for contrast between areas.")
+            .long("sharpen")
+            .takes_value(true)
+            .possible_values(&sharpen::FILTER_NAMES))
+        .arg(Arg::with_name("sharpen-amount")
+            .help("How strong the sharpening is, e.g. 0.5. By default it depends on how much the image was shrunk.")
+            .long("sharpen-amount")
+            .takes_value(true))
+        .arg(Arg::with_name("brightness")
+            .help("Make the image lighter or darker, from -100 to 100.")
+            .long("brightness")
+            .takes_value(true)
+            .allow_hyphen_values(true))
+        .arg(Arg::with_name("contrast")
+            .help("Raise or lower the contrast, from -100 to 100.")
+            .long("contrast")
+            .takes_value(true)
+            .allow_hyphen_values(true))
+        .arg(Arg::with_name("gamma")
+            .help("Apply a gamma curve. Values over 1 bring out detail in the shadows, values under 1 in the highlights.")
+            .long("gamma")
+            .takes_value(true))
+        .arg(Arg::with_name("saturation")
+            .help("Change how strong the colors are. 0 is grey, 1 leaves them alone and 2 doubles them.")
+            .long("saturation")
+            .takes_value(true))
+        .arg(Arg::with_name("levels")
+            .help("Set the black and white points as black:white, e.g. 20:230 makes everything under 20 black and over 230 white.")
+            .long("levels")
+            .takes_value(true)
+            .conflicts_with("auto-levels"))
+        .arg(Arg::with_name("auto-levels")
+            .help("Stretch the tones so the darkest part of the image is black and the lightest is white.")
+            .long("auto-levels"))
+        .arg(Arg::with_name("equalize")
+            .help("Spread the tones out evenly over the whole image (global), or separately in each part of it (clahe), which brings out local detail.")
+            .long("equalize")
+            .takes_value(true)
+            .possible_values(&["global", "clahe"]))
+        .arg(Arg::with_name("filter")
+            .help("Set the filter to use when resizing the image. The default, box, averages everything under each character; nearest is best for pixel art.")
+            .long("filter")
+            .takes_value(true)
+            .possible_values(&["box", "nearest", "triangle", "catmullrom", "gaussian", "lanczos3"]))
+        .arg(Arg::with_name("linear")
+            .help("Resize and dither in linear light, which keeps the brightness of the image right. The default, auto, does this for images with more than 256 colors, which are probably photos.")
+            .long("linear")
+            .takes_value(true)
+            .possible_values(&["auto", "on", "off"]))
         .arg(Arg::with_name("dither")
             .help("Set the dithering mode to use.")
             .short("d")
             .takes_value(true)
             .possible_values(&["none", "fs", "fs-vanilla", "fs-checkered", "ordered"])
         )
+        .arg(Arg::with_name("charset")
+            .help("Use your own characters for the ASCII art styles, from the least ink to the most, e.g. \" .:-=+*#%@\".")
+            .long("charset")
+            .takes_value(true)
+            .conflicts_with("ramp"))
+        .arg(Arg::with_name("ramp")
+            .help("Use one of the built-in sets of characters for the ASCII art styles.")
+            .long("ramp")
+            .takes_value(true)
+            .possible_values(&charset::RAMP_NAMES))
+        .arg(Arg::with_name("ink-levels")
+            .help("Match characters to shades by measuring how much ink each one uses instead of spacing them evenly.")
+            .long("ink-levels"))
+        .arg(Arg::with_name("invert")
+            .help("Flip the ASCII art styles around for light text on a dark background.")
+            .long("invert")
+            .conflicts_with("no-invert"))
+        .arg(Arg::with_name("no-invert")
+            .help("Don't flip the ASCII art styles, even if the terminal has a dark background.")
+            .long("no-invert"))
+        .arg(Arg::with_name("no-query")
+            .help("Don't send the terminal escape codes asking about its settings.")
+            .long("no-query"))
+        .arg(Arg::with_name("palette")
+            .help("Read the terminal's colors from a theme file (GIMP, Xresources, alacritty, kitty, Windows Terminal or a list of hex colors) or use a built-in palette (cga, gameboy, pico8). With -m truecolor, draw using only these colors.")
+            .long("palette")
+            .takes_value(true))
+        .arg(Arg::with_name("adaptive")
+            .help("In 256colors mode, make the best palette of this many colors (up to 240) for the image and load it into the terminal. The terminal's colors are put back when you press a key.")
+            .long("adaptive")
+            .takes_value(true))
+        .arg(Arg::with_name("color")
+            .help("Color the characters of the ASCII art styles using this many colors.")
+            .long("color")
+            .takes_value(true)
+            .possible_values(&["8", "16", "256", "truecolor"]))
+        .arg(Arg::with_name("edge-threshold")
+            .help("How much contrast (0-255) an edge needs to be drawn in edges mode.")
+            .long("edge-threshold")
+            .takes_value(true))
+        .arg(Arg::with_name("edge-blend")
+            .help("In edges mode, fill the space between edges with regular ASCII art.")
+            .long("edge-blend"))
         .get_matches();
 
-    // Get the input file name. Crash if not specified.
+    // Turn ANSI art back into a picture instead, which doesn't need any of
+    // the settings below.
+    if let Some(path) = matches.value_of("from-ansi") {
+        from_ansi(&matches, path);
+        return;
+    }
+
+    check_binary_output(&matches, matches.value_of("format").unwrap_or("ansi"));
+
+    // Get the input file name. Crash if not specified. Raw video always
+    // comes from stdin, which is called - like in most programs.
     let infile_name;
     if matches.is_present("INPUT") {
         infile_name = matches.value_of("INPUT").unwrap();
+    } else if matches.is_present("raw") {
+        infile_name = "-";
+    } else if matches.is_present("sequence") {
+        infile_name = matches.value_of("sequence").unwrap();
     } else {
         println!("No input file name supplied!");
         process::exit(1);
@@ -73,44 +290,572 @@ fn main() {
 
     // Get the dimensions of the terminal window. The code is rather lenghty
     // so it has its own method.
-    let (x, y): (u32, u32) = choose_dimensions(&matches);
+    let (mut x, y): (u32, u32) = choose_dimensions(&matches);
+    // ANSI art viewers are 80 columns wide, so that's as wide as the picture
+    // can be if it's going to be wrapped for them.
+    if matches.value_of("format") == Some("ans") && matches.is_present("wrap-80") {
+        x = x.min(80);
+    }
 
     // Handle mode inputs. If the user doesn't specify a mode, default to
     // ascii. Invalid values are handled by the library that handles arguments.
     let mode = matches.value_of("mode").unwrap_or("ascii");
 
-    // Get the aspect ratio. If not specified by the user, 0.5 is a reasonable
-    // default.
+    // Get the aspect ratio. If not specified by the user, work it out from
+    // the size of the terminal's characters in pixels, and if the terminal
+    // won't tell us, 0.5 is a reasonable default.
     let ratio: f32;
-    let default_ratio: f32 = 0.5;
+    let default_ratio = || term::cell_aspect_ratio(!matches.is_present("no-query")).unwrap_or(0.5);
     if matches.is_present("ratio") {
         match matches.value_of("ratio").unwrap().parse::<f32>() {
             Ok(n) => ratio = n,
             Err(_) => {
-                eprintln!("Invalid value `{}' for aspect ratio, defaulting to {}.", matches.value_of("ratio").unwrap(), default_ratio);
-                ratio = default_ratio;
+                ratio = default_ratio();
+                eprintln!("Invalid value `{}' for aspect ratio, defaulting to {}.", matches.value_of("ratio").unwrap(), ratio);
             },
         }
     } else {
-        ratio = default_ratio;
+        ratio = default_ratio();
     }
     
     // Get the dither mode. If not specified by the user, Floyd-Steinberg is
     // the default. The argument library handles invalid values so we don't
     // have to worry about that.
     let dither = matches.value_of("dither").unwrap_or("fs");
-    
 
-    // Open the input image file and resize it.
-    let inimg = image::open(&Path::new(&infile_name)).ok().expect("Opening image failed");
-    let img = resize(inimg, x, y, ratio);
+    // Get the characters to use for the ASCII art styles. Characters given
+    // by the user win over a built-in ramp, which wins over the ramp that
+    // goes with the chosen mode.
+    let ramp_result = match matches.value_of("charset") {
+        Some(chars) => charset::Ramp::from_chars(chars),
+        None => {
+            let default_ramp = match mode {
+                "pound" => "pound",
+                "ascii-simple" => "ascii-simple",
+                _ => "ascii",
+            };
+            charset::Ramp::named(matches.value_of("ramp").unwrap_or(default_ramp))
+        },
+    };
+    let mut ramp = match ramp_result {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    };
+    if matches.is_present("ink-levels") {
+        if let Err(e) = ramp.measure_ink() {
+            eprintln!("{} Using evenly spaced shades instead.", e);
+        }
+    }
+
+    // The ramps are made for dark text on a light background, so on a dark
+    // background they come out as a negative and have to be flipped. Unless
+    // the user tells us which way to go, try to find out what color the
+    // terminal's background is.
+    let ascii_style = !["8colors", "16colors", "8colors-shaded", "16colors-shaded", "256colors", "truecolor"].contains(&mode);
+    let invert;
+    if matches.is_present("invert") {
+        invert = true;
+    } else if matches.is_present("no-invert") || !ascii_style {
+        invert = false;
+    } else {
+        invert = term::background_is_dark(!matches.is_present("no-query")).unwrap_or(false);
+    }
+    if invert {
+        ramp.invert();
+    }
+
+    // Get the settings for the edges mode.
+    let default_threshold: f32 = 48.0;
+    let edge_threshold = match matches.value_of("edge-threshold") {
+        Some(s) => match s.parse::<f32>() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Invalid value `{}' for edge threshold, defaulting to {}.", s, default_threshold);
+                default_threshold
+            },
+        },
+        None => default_threshold,
+    };
+    let edge_blend = matches.is_present("edge-blend");
+
+    // Get the output format. The terminal is the default.
+    let format = matches.value_of("format").unwrap_or("ansi");
+
+    // Get the number of colors for an adaptive palette. This only works in
+    // 256 color mode, where we can redefine the terminal's palette.
+    let mut adaptive: Option<usize> = None;
+    if let Some(s) = matches.value_of("adaptive") {
+        match s.parse::<usize>() {
+            Ok(n) if (1..=240).contains(&n) => adaptive = Some(n),
+            _ => eprintln!("Invalid value `{}' for the number of adaptive colors, it must be from 1 to 240.", s),
+        }
+        if mode != "256colors" {
+            eprintln!("Adaptive palettes only work in 256colors mode, ignoring --adaptive.");
+            adaptive = None;
+        } else if format != "ansi" || matches.is_present("output") {
+            eprintln!("Adaptive palettes only work in the terminal, ignoring --adaptive.");
+            adaptive = None;
+        }
+    }
+
+    // Find out what the terminal's palette colors look like. The first 16
+    // colors vary a lot between terminals and the rest can be changed by
+    // programs, so the defaults are only a guess. A palette file from the
+    // user beats asking the terminal.
+    let color = matches.value_of("color");
+    let mut term_colors: Vec<Option<Color>> = vec![None; 256];
+    if (mode == "256colors" || color == Some("256")) && adaptive.is_none() && !matches.is_present("no-query") {
+        for (i, c) in term::palette_colors().into_iter().enumerate() {
+            term_colors[i] = c.map(|(r, g, b)| Color { r, g, b, a: 255 });
+        }
+    }
+    let mut user_palette = None;
+    let mut basic_colors = 16;
+    if let Some(path) = matches.value_of("palette") {
+        match palette::load(path) {
+            Ok(colors) => {
+                // A palette with fewer colors than the basic 16, like the
+                // Game Boy's, is all the colors the picture gets to use.
+                if colors.len() < 16 && colors.iter().all(|c| c.is_some()) {
+                    basic_colors = colors.len();
+                    if mode == "256colors" || color == Some("256") {
+                        eprintln!("The palette only has {} colors, but 256 color mode uses all 256 anyway. Try 16colors or truecolor mode to stick to the palette.", basic_colors);
+                    }
+                }
+                for (i, c) in colors.iter().take(256).enumerate() {
+                    if c.is_some() {
+                        term_colors[i] = *c;
+                    }
+                }
+                user_palette = Some(colors.into_iter().flatten().collect());
+            },
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            },
+        }
+    }
+
+    // Open the input. Video, image sequences and animated GIFs come out as
+    // lots of frames, and anything else as one frame. Video and sequences
+    // are read a frame at a time as they're played, since they could be
+    // longer than we have memory for.
+    let mut frames: Box<dyn Iterator<Item = animation::Frame>>;
+    let stream = infile_name == "-" || matches.is_present("sequence");
+    let fps = number_arg(&matches, "fps", 25.0).max(0.1) as f64;
+    if let Some(dir) = matches.value_of("sequence") {
+        match animation::load_sequence(dir, fps) {
+            Ok(s) => frames = Box::new(s),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            },
+        }
+    } else if let Some(size) = matches.value_of("raw") {
+        let (width, height) = match video::parse_size(size) {
+            Some(s) => s,
+            None => {
+                eprintln!("Invalid size `{}' for raw video, it should be like 640x360 and at most {}x{}.", size, video::MAX_SIZE, video::MAX_SIZE);
+                process::exit(1);
+            },
+        };
+        frames = Box::new(video::Raw::new(io::stdin(), width, height, fps));
+    } else if infile_name == "-" {
+        match video::Y4m::new(io::stdin()) {
+            Ok(v) => frames = Box::new(v),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            },
+        }
+    } else if animation::is_gif(infile_name) {
+        match animation::load_gif(infile_name) {
+            Ok(f) => frames = Box::new(f.into_iter()),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            },
+        }
+    } else {
+        let still = image::open(Path::new(&infile_name)).expect("Opening image failed");
+        frames = Box::new(vec![animation::Frame { image: still, delay: 0.0 }].into_iter());
+    }
+    let first = match frames.next() {
+        Some(f) => f,
+        None => {
+            eprintln!("There aren't any frames in the input.");
+            process::exit(1);
+        },
+    };
+    let second = frames.next();
+    let animated = second.is_some();
+    let orientation = if matches.is_present("no-exif") || stream { None } else { transform::exif_orientation(infile_name) };
+
+    // Averaging sRGB values makes mixed colors too dark, so photos are
+    // resized and dithered in linear light. Pixel art and drawings have few
+    // colors and usually look the way their artist wanted without it.
+    let linear = match matches.value_of("linear").unwrap_or("auto") {
+        "on" => true,
+        "off" => false,
+        _ => count_colors(&first.image, 257) > 256,
+    };
+
+    let opts = RenderOptions {
+        mode,
+        dither,
+        linear,
+        ramp,
+        color,
+        term_colors,
+        basic_colors,
+        user_palette,
+        edge_threshold,
+        edge_blend,
+    };
+
+    let mut max_upscale: Option<f32> = None;
+    if let Some(s) = matches.value_of("max-upscale") {
+        match s.parse::<f32>() {
+            Ok(n) if n > 0.0 => max_upscale = Some(n),
+            _ => eprintln!("Invalid value `{}' for the maximum upscale, ignoring it.", s),
+        }
+    }
+    let mut levels = None;
+    if let Some(s) = matches.value_of("levels") {
+        let mut parts = s.splitn(2, ':').map(|v| v.trim().parse::<u8>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(black)), Some(Ok(white))) if black < white => levels = Some((black, white)),
+            _ => eprintln!("Invalid value `{}' for levels, it should be black:white with numbers from 0 to 255. Ignoring it.", s),
+        }
+    }
+
+    // Everything that happens to the picture before it's rendered, which is
+    // done to every frame of an animation.
+    let prepare = |mut inimg: image::DynamicImage| -> image::DynamicImage {
+        // Turn photos the right way up first, so the crop is in the same
+        // place that the user sees it in other programs.
+        if let Some(orientation) = orientation {
+            inimg = transform::orient(inimg, orientation);
+        }
+        if let Some(spec) = matches.value_of("crop") {
+            let (width, height) = inimg.dimensions();
+            match transform::parse_crop(spec, width, height) {
+                Ok(rect) => inimg = transform::crop(inimg, rect),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                },
+            }
+        }
+        if let Some(degrees) = matches.value_of("rotate") {
+            inimg = transform::rotate(inimg, degrees.parse().unwrap_or(0));
+        }
+        if let Some(direction) = matches.value_of("flip") {
+            inimg = transform::flip(inimg, direction);
+        }
+
+        // Resize the image to fit the terminal.
+        let resize_opts = ResizeOptions {
+            width: x,
+            height: y,
+            aspect: ratio,
+            scale: matches.value_of("scale").unwrap_or("fit"),
+            max_upscale,
+            filter: matches.value_of("filter").unwrap_or("box"),
+            linear,
+        };
+        let (source_width, source_height) = inimg.dimensions();
+        let mut img = resize(inimg, &resize_opts);
+
+        // Sharpen the image to make up for the detail lost by shrinking it.
+        // The vertical direction usually loses the most, since characters
+        // are tall.
+        if let Some(filter) = matches.value_of("sharpen") {
+            let shrunk = (source_width as f32 / img.width() as f32).max(source_height as f32 / img.height() as f32);
+            let amount = number_arg(&matches, "sharpen-amount", sharpen::auto_amount(shrunk));
+            img = sharpen::sharpen(img, filter, amount);
+        }
+
+        // Adjust the tones and colors of the resized image.
+        let adjustments = adjust::Adjustments {
+            brightness: number_arg(&matches, "brightness", 0.0),
+            contrast: number_arg(&matches, "contrast", 0.0),
+            gamma: number_arg(&matches, "gamma", 1.0),
+            saturation: number_arg(&matches, "saturation", 1.0),
+            levels,
+            auto_levels: matches.is_present("auto-levels"),
+            equalize: matches.value_of("equalize"),
+        };
+        adjust::apply(img, &adjustments)
+    };
+
+    // Work out how far to move the image over to line it up with the middle
+    // or the right of the terminal.
+    let align = |width: u32| -> u32 {
+        match matches.value_of("align").unwrap_or("left") {
+            "center" => x.saturating_sub(width) / 2,
+            "right" => x.saturating_sub(width),
+            _ => 0,
+        }
+    };
+
+    // Play animations in the terminal, recording them if we're asked to.
+    // Frames of video that are already late by the time we get to them are
+    // dropped, so slow rendering doesn't make it fall further and further
+    // behind.
+    if matches.is_present("record") && format != "ansi" {
+        eprintln!("Recordings can only be made in the terminal, ignoring --record.");
+    }
+    let record = matches.value_of("record").filter(|_| format == "ansi");
+    if format == "ansi" && (animated || record.is_some()) {
+        if adaptive.is_some() {
+            eprintln!("Adaptive palettes don't work with animations, ignoring --adaptive.");
+        }
+        // Frames written to a file are written as fast as they can be,
+        // since only someone watching the terminal needs them to wait.
+        let wait = !matches.is_present("output") && termion::is_tty(&io::stdout());
+        let out: Box<dyn Write> = match matches.value_of("output") {
+            Some(path) => match fs::File::create(path) {
+                Ok(f) => Box::new(io::BufWriter::new(f)),
+                Err(e) => {
+                    eprintln!("Can't write `{}': {}", path, e);
+                    process::exit(1);
+                },
+            },
+            None => Box::new(io::stdout()),
+        };
+        let mut player = animation::Player::new(out, wait, record.is_some(), infile_name == "-");
+        for frame in iter::once(first).chain(second).chain(frames) {
+            if player.behind(frame.delay) {
+                player.skip(frame.delay);
+                continue;
+            }
+            let img = prepare(frame.image);
+            let indent = align(img.width());
+            player.show(&render(img, &opts).indent(indent), frame.delay);
+        }
+        if let Some(path) = record {
+            write_output(Some(path), export::cast::to_cast(x, y, &player.events).as_bytes());
+        }
+        return;
+    }
+    if animated {
+        eprintln!("Only the first frame of the animation can be written as {}.", format);
+    }
+    let img = prepare(first.image);
+    let indent = align(img.width());
+
+    // Render the image to the terminal, or in another format.
+    match adaptive {
+        Some(n) => show_adaptive(img, opts.dither, opts.linear, n, indent),
+        None => {
+            let grid = render(img, &opts).indent(indent);
+            let theme = export_theme(&opts.term_colors, invert);
+            match export_grid(&matches, format, &grid, &theme, infile_name, mode) {
+                Ok(output) => write_output(matches.value_of("output"), &output),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                },
+            }
+        },
+    }
+}
+
+// Write a grid in one of the formats for files. name is the name of the
+// picture, for the formats that have somewhere to put it.
+fn export_grid(matches: &clap::ArgMatches, format: &str, grid: &grid::Grid, theme: &export::Theme, name: &str, mode: &str) -> Result<Vec<u8>, String> {
+    Ok(match format {
+        "ans" => {
+            let title = Path::new(name).file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            let sauce = export::ans::Sauce {
+                title: matches.value_of("title").unwrap_or(title),
+                author: matches.value_of("author").unwrap_or(""),
+            };
+            let sauce = if matches.is_present("sauce") { Some(&sauce) } else { None };
+            export::ans::to_ans(grid, theme, matches.is_present("wrap-80"), sauce)
+        },
+        "html" => export::html::to_html(grid, theme, name, matches.is_present("fragment"), matches.is_present("css-classes")).into_bytes(),
+        "svg" => export::svg::to_svg(grid, theme).into_bytes(),
+        "png" => export::png::to_png(grid, theme).map_err(|e| format!("Encoding the PNG failed: {}", e))?,
+        "irc" => export::chat::to_irc(grid, theme, matches.value_of("irc-colors") == Some("99")).into_bytes(),
+        "bbcode" => export::chat::to_bbcode(grid, theme).into_bytes(),
+        "json" => export::data::to_json(grid, theme, mode).into_bytes(),
+        "cells" => export::data::to_binary(grid, theme, mode)?,
+        "discord" => {
+            let text = export::chat::to_discord(grid, theme);
+            if text.chars().count() > export::chat::DISCORD_LIMIT {
+                eprintln!("Warning: This is {} characters long, which is too long for one Discord message. Try a smaller size with -x and -y.", text.chars().count());
+            }
+            text.into_bytes()
+        },
+        _ => grid::to_ansi(grid).into_bytes(),
+    })
+}
+
+// Read an ANSI art file into a grid and write it out as a picture. The
+// colors are the classic ones on black, like ANSI art viewers use.
+fn from_ansi(matches: &clap::ArgMatches, path: &str) {
+    let format = matches.value_of("format").unwrap_or("png");
+    check_binary_output(matches, format);
+    let data = match fs::read(path) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Can't read `{}': {}", path, e);
+            process::exit(1);
+        },
+    };
+    let width = match matches.value_of("ansi-width") {
+        Some(s) => match s.parse::<u32>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                eprintln!("Invalid value `{}' for the ANSI art width, using 80.", s);
+                Some(80)
+            },
+        },
+        None => Some(import::sauce_width(&data).unwrap_or(80)),
+    };
+    let grid = match import::parse_ansi(&data, width) {
+        Ok(g) => g,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    };
+    let theme = export_theme(&[], true);
+    match export_grid(matches, format, &grid, &theme, path, "ansi") {
+        Ok(output) => write_output(matches.value_of("output"), &output),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    }
+}
+
+// Stop before writing a binary format to the terminal, which would only make
+// a mess of it. Pipes and files are fine.
+fn check_binary_output(matches: &clap::ArgMatches, format: &str) {
+    if (format == "png" || format == "cells") && !matches.is_present("output") && termion::is_tty(&io::stdout()) {
+        eprintln!("River won't write {} to the terminal. Give it a file with -o, or pipe it somewhere.", format);
+        process::exit(1);
+    }
+}
 
-    // Render the image to the terminal.
-    render(img, mode, dither);
+// Write the finished picture to a file, or to the terminal if there's no
+// file name.
+fn write_output(path: Option<&str>, output: &[u8]) {
+    let result = match path {
+        Some(p) => fs::write(p, output).map_err(|e| format!("Can't write `{}': {}", p, e)),
+        // Someone closing the pipe we're writing to isn't worth a message.
+        None => io::stdout().write_all(output).map_err(|_| String::new()),
+    };
+    if let Err(e) = result {
+        if !e.is_empty() {
+            eprintln!("{}", e);
+        }
+        process::exit(1);
+    }
+}
+
+// Work out what the terminal's colors really look like, for the formats that
+// aren't shown in a terminal. The text and background colors are black and
+// white from the palette, the right way round for the ASCII art styles.
+fn export_theme(term_colors: &[Option<Color>], dark: bool) -> export::Theme {
+    let ansi = ansi_palette();
+    let standard = generate_256colors_palette();
+    let palette: Vec<(u8, u8, u8)> = (0..256)
+        .map(|i| {
+            let c = term_colors.get(i).cloned().unwrap_or(None)
+                .unwrap_or_else(|| if i < 16 { ansi[i] } else { standard[i - 16] });
+            (c.r, c.g, c.b)
+        })
+        .collect();
+
+    let (fg, bg) = if dark {
+        (palette[7], palette[0])
+    } else {
+        (palette[0], palette[15])
+    };
+    export::Theme { palette, fg, bg }
+}
+
+// Show an image in 256 colors with a palette made just for it. The palette
+// is loaded into the terminal's colors 16 and up, and shown as a strip of
+// swatches under the image. The terminal's colors have to stay changed for
+// as long as the image is on the screen, so we wait for a key before putting
+// them back.
+fn show_adaptive(img: image::DynamicImage, dither: &str, linear: bool, colors: usize, indent: u32) {
+    let palette = adaptive_palette(&img, colors);
+
+    let mut setup = String::new();
+    let mut restore = String::new();
+    for (i, c) in palette.iter().enumerate() {
+        setup.push_str(&term::set_palette_color(16 + i as u8, c.r, c.g, c.b));
+        restore.push_str(&term::reset_palette_color(16 + i as u8));
+    }
+
+    let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
+    let indexed_data = quantize(img, &palette, dither, linear);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel_color = indexed_data[(width*y + x) as usize] + 16;
+            grid.set(x, y, grid::Cell::block(grid::Ink::Indexed(pixel_color as u8)));
+        }
+    }
+
+    // Two cells per swatch, wrapped to the width of the image.
+    let per_row = (width as usize / 2).max(1);
+    let rows = palette.len().div_ceil(per_row);
+    let mut swatches = grid::Grid::new((per_row.min(palette.len()) * 2) as u32, rows as u32);
+    for i in 0..palette.len() {
+        let cell = grid::Cell::block(grid::Ink::Indexed(16 + i as u8));
+        let (x, y) = ((i % per_row * 2) as u32, (i / per_row) as u32);
+        swatches.set(x, y, cell);
+        swatches.set(x + 1, y, cell);
+    }
+
+    print!("{}{}{}", setup, grid::to_ansi(&grid.indent(indent)), grid::to_ansi(&swatches.indent(indent)));
+    if termion::is_tty(&io::stdout()) {
+        eprint!("Press any key to restore the terminal's colors.");
+        let _ = io::stderr().flush();
+        term::wait_for_key();
+        eprintln!();
+    }
+    print!("{}", restore);
+}
+
+// Pick the best palette of the given size for an image, using exoquant's
+// k-means optimizer.
+fn adaptive_palette(img: &image::DynamicImage, colors: usize) -> Vec<Color> {
+    let histogram = image_to_exoquant(img.clone()).into_iter().collect();
+    let colorspace = SimpleColorSpace::default();
+    let optimizer = optimizer::KMeans;
+    let palette = generate_palette(&histogram, &colorspace, &optimizer, colors);
+    optimizer.optimize_palette(&colorspace, &palette, &histogram, 8)
+}
+
+// Read a number argument, falling back to the default if it's missing or
+// isn't a number.
+fn number_arg(matches: &clap::ArgMatches, name: &str, default: f32) -> f32 {
+    match matches.value_of(name) {
+        Some(s) => match s.parse::<f32>() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Invalid value `{}' for {}, defaulting to {}.", s, name, default);
+                default
+            },
+        },
+        None => default,
+    }
 }
 
 // Determine the dimensions to print the image with, based on the arguments
 // given, the size of the terimanl, and the default size if all else fails.
+#[allow(clippy::needless_return, clippy::unnecessary_unwrap)]
 fn choose_dimensions(matches: &clap::ArgMatches) -> (u32, u32) {
     // Somewhat messy but does what I want. Definitely some technical debt in
     // here.
@@ -180,211 +925,438 @@ fn choose_dimensions(matches: &clap::ArgMatches) -> (u32, u32) {
     return (x, y);
 }
 
+// Settings that control how the image is fitted to the terminal.
+struct ResizeOptions<'a> {
+    // The size of the terminal.
+    width: u32,
+    height: u32,
+    // The aspect ratio (width/height) of the terminal's characters.
+    aspect: f32,
+    // How the image goes into the terminal: fit it inside, fill the whole
+    // terminal and crop off what doesn't fit, stretch it to the terminal's
+    // shape, match only the width or the height, or leave it at one row (or
+    // column) per pixel.
+    scale: &'a str,
+    // How many times the image can be enlarged, if there's a limit.
+    max_upscale: Option<f32>,
+    filter: &'a str,
+    linear: bool,
+}
+
 // Resize an image for display in the terminal, based on the aspect ratio
-// (width/height) of the terminal characters and the maximum size.
-fn resize(inimg: image::DynamicImage, x: u32, y: u32, aspect: f32) -> image::DynamicImage {
-    let xmax: u32 = x;
-    let ymax: u32 = y;
+// of the terminal characters and the size of the terminal.
+#[allow(clippy::needless_return, clippy::unnecessary_cast)]
+fn resize(inimg: image::DynamicImage, opts: &ResizeOptions) -> image::DynamicImage {
+    let xmax: u32 = opts.width;
+    let ymax: u32 = opts.height;
+    let aspect: f32 = opts.aspect;
+    let scale_policy = opts.scale;
+    let (filter, linear) = (opts.filter, opts.linear);
     let (width, height) = inimg.dimensions();
     let xi: u32 = width as u32;
     let yi: u32 = height as u32;
+
+    // The size of the image if every pixel were a character, stretched the
+    // other way to make up for the shape of the characters.
+    let xeff: f32;
+    let yeff: f32;
     if aspect > 1.0 {
-        let xeff: f32 = xi as f32;
-        let yeff: f32 = (yi as f32) * aspect;
-        let xscale: f32 = (xmax as f32) / xeff;
-        let yscale: f32 = (ymax as f32) / yeff;
-        let scale: f32;
-        if xscale < yscale {
-            scale = xscale;
-        } else {
-            scale = yscale;
-        }
-        let xf: u32 = f32::trunc(xeff*scale) as u32;
-        let yf: u32 = f32::trunc(yeff*scale) as u32;
-        // Use nearest neighbor resizing to make it as sharp as possible.
-        return inimg.resize_exact(xf, yf, FilterType::Nearest);
-    } else if aspect < 1.0 {
-        let xeff: f32 = (xi as f32) / aspect;
-        let yeff: f32 = yi as f32;
-        let xscale: f32 = (xmax as f32) / xeff;
-        let yscale: f32 = (ymax as f32) / yeff;
-        let scale: f32;
-        if xscale < yscale {
-            scale = xscale;
-        } else {
-            scale = yscale;
-        }
-        let xf: u32 = f32::trunc(xeff*scale) as u32;
-        let yf: u32 = f32::trunc(yeff*scale) as u32;
-        return inimg.resize_exact(xf, yf, FilterType::Nearest);
+        xeff = xi as f32;
+        yeff = (yi as f32) * aspect;
     } else {
-        return inimg.resize(xmax, ymax, FilterType::Nearest);
+        xeff = (xi as f32) / aspect;
+        yeff = yi as f32;
+    }
+    let xscale: f32 = (xmax as f32) / xeff;
+    let yscale: f32 = (ymax as f32) / yeff;
+    let limit = |scale: f32| match opts.max_upscale {
+        Some(m) => scale.min(m),
+        None => scale,
+    };
+
+    // Every pixel gets one character, whatever shape the characters are.
+    if scale_policy == "none" {
+        return inimg;
     }
+
+    if scale_policy == "stretch" {
+        let xf: u32 = f32::trunc(xeff*limit(xscale)) as u32;
+        let yf: u32 = f32::trunc(yeff*limit(yscale)) as u32;
+        return resample(&inimg, xf, yf, filter, linear);
+    }
+
+    let scale: f32 = limit(match scale_policy {
+        "fill" => xscale.max(yscale),
+        "width" => xscale,
+        "height" => yscale,
+        _ => xscale.min(yscale),
+    });
+    let xf: u32 = f32::trunc(xeff*scale) as u32;
+    let yf: u32 = f32::trunc(yeff*scale) as u32;
+    let img = resample(&inimg, xf, yf, filter, linear);
+
+    if scale_policy == "fill" {
+        // Cut the middle out of the image, it's at least as big as the
+        // terminal in one direction.
+        let (w, h) = img.dimensions();
+        let (cw, ch) = (w.min(xmax).max(1), h.min(ymax).max(1));
+        let mut img = img;
+        return img.crop((w - cw) / 2, (h - ch) / 2, cw, ch);
+    }
+    return img;
 }
 
-// Print the input image file.
-fn render(img: image::DynamicImage, mode: &str, dither: &str) {
-    // Pick the right rendering method based on what the user wants.
-    match mode {
-        "pound" => render_pound(img, dither),
-        "ascii" => render_ascii(img, dither),
-        "ascii-simple" => render_ascii_simple(img, dither),
-        "8colors" => render_8colors(img, dither),
-        "16colors" => render_16colors(img, dither),
-        "256colors" => render_256colors(img, dither),
-        "truecolor" => render_truecolor(img),
-        _ => render_ascii(img, dither),
+// Resize an image to exactly the given size with the chosen filter. The box
+// filter averages every source pixel that falls into each output pixel,
+// which is the most faithful way to shrink a big image down to a few
+// characters. Nearest neighbor is the sharpest, which suits pixel art. With
+// linear set, the other filters mix colors in linear light.
+fn resample(img: &image::DynamicImage, width: u32, height: u32, filter: &str, linear: bool) -> image::DynamicImage {
+    // Don't make an empty image, the renderers can't do anything with it.
+    let width = width.max(1);
+    let height = height.max(1);
+    let (src_width, src_height) = img.dimensions();
+
+    let filter_type = match filter {
+        "nearest" => FilterType::Nearest,
+        "triangle" => FilterType::Triangle,
+        "catmullrom" => FilterType::CatmullRom,
+        "gaussian" => FilterType::Gaussian,
+        "lanczos3" => FilterType::Lanczos3,
+        _ => {
+            // Averaging doesn't do anything useful when enlarging, every
+            // output pixel would just be a copy of one source pixel.
+            if width >= src_width && height >= src_height {
+                FilterType::Nearest
+            } else {
+                return image::ImageRgba8(box_resize(&img.to_rgba(), width, height, linear));
+            }
+        },
+    };
+    // Nearest neighbor doesn't mix colors, so linear light makes no
+    // difference to it.
+    if linear && filter != "nearest" {
+        return image::ImageRgba8(linear_resize(&img.to_rgba(), width, height, filter_type));
     }
+    img.resize_exact(width, height, filter_type)
 }
 
-// Display an image in the terminal by printing an array of spaces and pounds.
-fn render_pound(img: image::DynamicImage, dither: &str) {
-    let palette = vec![
-        Color { r: 0, g: 0, b: 0, a: 255 },
-        Color { r: 255, g: 255, b: 255, a: 255 },
-    ];
+// Resize an image with one of the image library's filters in linear light.
+// The image is converted to 16 bits per channel first, so the dark colors
+// don't get crushed together.
+fn linear_resize(img: &image::RgbaImage, width: u32, height: u32, filter: FilterType) -> image::RgbaImage {
+    let wide: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y).data;
+        let channel = |v: u8| (srgb_to_linear(v) * 65535.0).round() as u16;
+        Rgba { data: [channel(p[0]), channel(p[1]), channel(p[2]), p[3] as u16 * 257] }
+    });
 
-    let (width, height) = img.dimensions();
+    let resized = image::imageops::resize(&wide, width, height, filter);
 
-    let quant_img = quantize(img, &palette, dither);
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let p = resized.get_pixel(x, y).data;
+        let channel = |v: u16| linear_to_srgb(v as f32 / 65535.0);
+        Rgba { data: [channel(p[0]), channel(p[1]), channel(p[2]), (p[3] / 257) as u8] }
+    })
+}
 
-    for y in 0..height {
-        for x in 0..width {
-            let pixel_color = quant_img[(width*y + x) as usize];
-            match pixel_color {
-                0 => print!("#"),
-                1 => print!(" "),
-                _ => print!(" "),
+// Shrink an image by averaging. Each output pixel covers a rectangle of the
+// source image and gets the average of everything in it, with the pixels on
+// the border weighted by how much of them is inside. The two directions are
+// done one after the other since that's much faster. With linear set the
+// averaging is done in linear light.
+fn box_resize(img: &image::RgbaImage, width: u32, height: u32, linear: bool) -> image::RgbaImage {
+    let (src_width, src_height) = img.dimensions();
+    let to_light: Vec<f32> = (0..256).map(|v| srgb_to_linear(v as u8) * 255.0).collect();
+    let (row_len, out_row_len) = (src_width as usize * 4, width as usize * 4);
+
+    // Squash the rows one at a time, so only one row of the source is ever
+    // turned into floats instead of the whole (possibly huge) photo.
+    let columns = box_weights(src_width as usize, width as usize);
+    let mut row = vec![0.0f32; row_len];
+    let mut horizontal = vec![0.0f32; out_row_len * src_height as usize];
+    for (y, out) in horizontal.chunks_mut(out_row_len).enumerate() {
+        let pixels = &img.as_ref()[y*row_len..(y + 1)*row_len];
+        for (i, (v, &p)) in row.iter_mut().zip(pixels).enumerate() {
+            *v = if linear && i % 4 != 3 { to_light[p as usize] } else { p as f32 };
+        }
+        for (x, &(first, ref weights)) in columns.iter().enumerate() {
+            for (k, &weight) in weights.iter().enumerate() {
+                for c in 0..4 {
+                    out[x*4 + c] += row[(first + k)*4 + c] * weight;
+                }
             }
         }
-        println!();
     }
+
+    // Then mix the squashed rows together.
+    let mut both = vec![0.0f32; out_row_len * height as usize];
+    for (out, &(first, ref weights)) in both.chunks_mut(out_row_len).zip(box_weights(src_height as usize, height as usize).iter()) {
+        for (k, &weight) in weights.iter().enumerate() {
+            let line = &horizontal[(first + k)*out_row_len..(first + k + 1)*out_row_len];
+            for (v, &h) in out.iter_mut().zip(line) {
+                *v += h * weight;
+            }
+        }
+    }
+
+    let data = both.into_iter()
+        .enumerate()
+        .map(|(i, v)| if linear && i % 4 != 3 { linear_to_srgb(v / 255.0) } else { v.round().clamp(0.0, 255.0) as u8 })
+        .collect();
+    image::ImageBuffer::from_raw(width, height, data).unwrap()
 }
 
-// Display an image using an ASCII art style.
-fn render_ascii(img: image::DynamicImage, dither: &str) {
-    let palette = vec![
-        Color { r: 0, g: 0, b: 0, a: 255 },
-        Color { r: 32, g: 32, b: 32, a: 255 },
-        Color { r: 64, g: 64, b: 64, a: 255 },
-        Color { r: 96, g: 96, b: 96, a: 255 },
-        Color { r: 128, g: 128, b: 128, a: 255 },
-        Color { r: 160, g: 160, b: 160, a: 255 },
-        Color { r: 192, g: 192, b: 192, a: 255 },
-        Color { r: 224, g: 224, b: 224, a: 255 },
-        Color { r: 255, g: 255, b: 255, a: 255 },
-     ];
+// Work out which source pixels go into each output pixel when shrinking a
+// line of old_len pixels to new_len, as the first one and how much each of
+// them counts. The weights of an output pixel add up to one.
+fn box_weights(old_len: usize, new_len: usize) -> Vec<(usize, Vec<f32>)> {
+    let scale = old_len as f32 / new_len as f32;
+    (0..new_len)
+        .map(|i| {
+            // The part of the source this output pixel covers.
+            let start = i as f32 * scale;
+            let end = start + scale;
+            let first = start.floor() as usize;
+            let last = (end.ceil() as usize).min(old_len);
+            let weights = (first..last)
+                .map(|j| (end.min(j as f32 + 1.0) - start.max(j as f32)).max(0.0) / scale)
+                .collect();
+            (first, weights)
+        })
+        .collect()
+}
 
-    let (width, height) = img.dimensions();
+// Settings that control how the image is drawn.
+struct RenderOptions<'a> {
+    mode: &'a str,
+    dither: &'a str,
+    // Whether to dither in linear light.
+    linear: bool,
+    // The characters for the ASCII art styles.
+    ramp: charset::Ramp,
+    // How many colors to use for the characters in the ASCII art styles, or
+    // None to leave them in the terminal's default color.
+    color: Option<&'a str>,
+    // The colors of the terminal's 256 color palette, where we know them.
+    term_colors: Vec<Option<Color>>,
+    // How many of the basic 16 colors there are to use, which is fewer if
+    // the user's palette is smaller than that.
+    basic_colors: usize,
+    // The colors from the user's palette file, if they gave us one.
+    user_palette: Option<Vec<Color>>,
+    edge_threshold: f32,
+    edge_blend: bool,
+}
+
+// Draw the image into a grid of character cells.
+fn render(img: image::DynamicImage, opts: &RenderOptions) -> grid::Grid {
+    // Pick the right rendering method based on what the user wants.
+    match opts.mode {
+        "8colors" => render_basic(img, opts.dither, opts.linear, &basic_palette(opts, 8)),
+        "16colors" => render_basic(img, opts.dither, opts.linear, &basic_palette(opts, 16)),
+        "8colors-shaded" => render_shaded(img, opts.dither, opts.linear, &basic_palette(opts, 8)),
+        "16colors-shaded" => render_shaded(img, opts.dither, opts.linear, &basic_palette(opts, 16)),
+        "256colors" => render_256colors(img, opts.dither, opts.linear, &opts.term_colors),
+        "truecolor" => match opts.user_palette {
+            Some(ref palette) => render_palette(img, opts.dither, opts.linear, palette),
+            None => render_truecolor(img),
+        },
+        _ => {
+            // Everything else is an ASCII art style.
+            let mut grid = match opts.mode {
+                "edges" => render_edges(&img, opts.dither, opts.linear, &opts.ramp, opts.edge_threshold, opts.edge_blend),
+                _ => render_ascii(&img, opts.dither, opts.linear, &opts.ramp),
+            };
 
-    let quant_img = quantize(img, &palette, dither);
+            // Color in the characters if the user asked for it. Spaces have
+            // no ink so we leave them alone, which saves on escape codes.
+            if let Some(depth) = opts.color {
+                let colors = foreground_colors(&img, depth, opts);
+                for (cell, fg) in grid.cells.iter_mut().zip(colors) {
+                    if cell.ch != ' ' {
+                        cell.fg = fg;
+                    }
+                }
+            }
+
+            grid
+        },
+    }
+}
 
+// Display an image using an ASCII art style, picking a character from the
+// ramp for each pixel.
+fn render_ascii(img: &image::DynamicImage, dither: &str, linear: bool, ramp: &charset::Ramp) -> grid::Grid {
+    let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
+
+    let quant_img = quantize(img.clone(), &ramp.palette, dither, linear);
 
     for y in 0..height {
         for x in 0..width {
             let pixel_color = quant_img[(width*y + x) as usize];
-            match pixel_color {
-                0 => print!("W"),
-                1 => print!("O"),
-                2 => print!("L"),
-                3 => print!(";"),
-                4 => print!(":"),
-                5 => print!("'"),
-                6 => print!("-"),
-                7 => print!(" "),
-                _ => print!(" "),
-            }
+            grid.set(x, y, grid::Cell::glyph(ramp.glyphs[pixel_color]));
         }
-        println!();
     }
+
+    grid
 }
 
+// Display an image as line art, drawing the edges in the image with
+// characters that follow their direction. Optionally the gaps between the
+// edges are filled in with the regular ASCII art style.
+fn render_edges(img: &image::DynamicImage, dither: &str, linear: bool, ramp: &charset::Ramp, threshold: f32, blend: bool) -> grid::Grid {
+    let (width, height) = img.dimensions();
 
-// Display an image using an ASCII art style that's somewhat simpler than the
-// regular one.
-fn render_ascii_simple(img: image::DynamicImage, dither: &str) {
-    let palette = vec![
-        Color { r: 0, g: 0, b: 0, a: 255 },
-        Color { r: 64, g: 64, b: 64, a: 255 },
-        Color { r: 128, g: 128, b: 128, a: 255 },
-        Color { r: 160, g: 160, b: 160, a: 255 },
-        Color { r: 255, g: 255, b: 255, a: 255 },
-     ];
+    let edges = edges::detect_edges(img, threshold);
+
+    let mut grid = if blend {
+        render_ascii(img, dither, linear, ramp)
+    } else {
+        grid::Grid::new(width, height)
+    };
+
+    for (cell, edge) in grid.cells.iter_mut().zip(edges) {
+        if let Some(c) = edge {
+            cell.ch = c;
+        }
+    }
+
+    grid
+}
 
+// Pick a text color for every pixel, for the colored ASCII art styles. The
+// depth is how many colors the terminal can show.
+fn foreground_colors(img: &image::DynamicImage, depth: &str, opts: &RenderOptions) -> Vec<grid::Ink> {
+    let (dither, linear, term_colors) = (opts.dither, opts.linear, &opts.term_colors);
+    match depth {
+        "8" | "16" => {
+            let palette = basic_palette(opts, if depth == "8" { 8 } else { 16 });
+            quantize(img.clone(), &palette, dither, linear).into_iter()
+                .map(|i| grid::Ink::Indexed(i as u8))
+                .collect()
+        },
+        "256" => {
+            let (palette, indexes) = palette_256colors(term_colors);
+            quantize(img.clone(), &palette, dither, linear).into_iter()
+                .map(|i| grid::Ink::Indexed(indexes[i]))
+                .collect()
+        },
+        _ => {
+            image_to_exoquant(img.clone()).into_iter()
+                .map(|c| grid::Ink::Rgb(c.r, c.g, c.b))
+                .collect()
+        },
+    }
+}
+
+// Display an image using ANSI color, with the first 8 or 16 colors of the
+// terminal's palette.
+fn render_basic(img: image::DynamicImage, dither: &str, linear: bool, palette: &[Color]) -> grid::Grid {
     let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
 
-    let quant_img = quantize(img, &palette, dither);
+    let indexed_data = quantize(img, palette, dither, linear);
 
     for y in 0..height {
         for x in 0..width {
-            let pixel_color = quant_img[(width*y + x) as usize];
-            match pixel_color {
-                0 => print!("W"),
-                1 => print!("O"),
-                2 => print!("o"),
-                3 => print!(":"),
-                4 => print!(" "),
-                _ => print!(" "),
-            }
+            let pixel_color = indexed_data[(width*y + x) as usize];
+            grid.set(x, y, grid::Cell::block(grid::Ink::Indexed(pixel_color as u8)));
         }
-        println!();
     }
+
+    grid
 }
 
-// Display an image using ANSI color.
-fn render_8colors(img: image::DynamicImage, dither: &str) {
-    // This array is the palette of color values for the eight basic terminal
-    // colors. In terms of data types, it's an array of exoquant::Color
-    // structs. These values the values used in xterm (According to
-    // https://jonasjacek.github.io/colors/ ) but are a reasonable
-    // approximation for terminals in general.
-    let palette = vec![
-        Color { r: 0, g: 0, b: 0, a: 255 },
-        Color { r: 128, g: 0, b: 0, a: 255 },
-        Color { r: 0, g: 128, b: 0, a: 255 },
-        Color { r: 128, g: 128, b: 0, a: 255 },
-        Color { r: 0, g: 0, b: 128, a: 255 },
-        Color { r: 128, g: 0, b: 128, a: 255 },
-        Color { r: 0, g: 128, b: 128, a: 255 },
-        Color { r: 192, g: 192, b: 192, a: 255 },
-    ];
+// Display an image using the basic ANSI colors, but get more colors out of
+// them by mixing pairs of colors with the shade characters. A light shade in
+// red on a blue background looks like a bluish purple from a distance, so
+// every pair of colors gives three more in-between colors to pick from.
+fn render_shaded(img: image::DynamicImage, dither: &str, linear: bool, base: &[Color]) -> grid::Grid {
+    let (palette, cells) = shaded_palette(base);
 
     let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
 
-    let indexed_data = quantize(img, &palette, dither);
+    let indexed_data = quantize(img, &palette, dither, linear);
 
     for y in 0..height {
         for x in 0..width {
-            let pixel_color = indexed_data[(width*y + x) as usize];
-            match pixel_color {
-                0 => print!("{} ", color::Bg(color::Black)),
-                1 => print!("{} ", color::Bg(color::Red)),
-                2 => print!("{} ", color::Bg(color::Green)),
-                3 => print!("{} ", color::Bg(color::Yellow)),
-                4 => print!("{} ", color::Bg(color::Blue)),
-                5 => print!("{} ", color::Bg(color::Magenta)),
-                6 => print!("{} ", color::Bg(color::Cyan)),
-                7 => print!("{} ", color::Bg(color::White)),
-                // We _should_ only get colors 0 through 7 but a little
-                // defensive programming never hurts.
-                _ => print!("{} ", color::Bg(color::White)),
+            grid.set(x, y, cells[indexed_data[(width*y + x) as usize]]);
+        }
+    }
+
+    grid
+}
+
+// Build the extended palette for the shaded modes. Each color in it comes
+// with the cell that shows it: a plain colored space for the base colors, or
+// a shade character in one color on a background of another for the mixed
+// ones.
+fn shaded_palette(base: &[Color]) -> (Vec<Color>, Vec<grid::Cell>) {
+    // The shade characters and roughly how much of the cell they cover.
+    let shades = [('\u{2591}', 0.25), ('\u{2592}', 0.5), ('\u{2593}', 0.75)];
+
+    let mut palette = Vec::new();
+    let mut cells = Vec::new();
+    for (i, &color) in base.iter().enumerate() {
+        palette.push(color);
+        cells.push(grid::Cell::block(grid::Ink::Indexed(i as u8)));
+    }
+
+    // A dark shade in one color on another is the same as a light shade with
+    // the colors swapped, so each pair only needs to be done one way round.
+    for bg in 0..base.len() {
+        for fg in bg + 1..base.len() {
+            for &(ch, coverage) in shades.iter() {
+                let color = mix(base[fg], base[bg], coverage);
+                if palette.contains(&color) {
+                    continue;
+                }
+                palette.push(color);
+                cells.push(grid::Cell {
+                    ch,
+                    fg: grid::Ink::Indexed(fg as u8),
+                    bg: grid::Ink::Indexed(bg as u8),
+                });
             }
         }
-        // Reset colors at the end of each line. If we don't do this, the
-        // color of the rightmost pixel in each line is extended to the right
-        // edge of the screen.
-        println!("{}", color::Bg(color::Reset));
     }
+
+    (palette, cells)
 }
 
-// Display an image using ANSI color.
-fn render_16colors(img: image::DynamicImage, dither: &str) {
-    // This array is the palette of color values for the 16 basic terminal
-    // colors. In terms of data types, it's an array of exoquant::Color
-    // structs. These values the values used in xterm (According to
-    // https://jonasjacek.github.io/colors/ ) but are a reasonable
-    // approximation for terminals in general.
-    let palette = vec![
+// Mix two colors the way the eye does when it sees a fine pattern of them,
+// with the given amount of the first one. This has to be done in linear
+// light, otherwise the mixes come out too dark.
+fn mix(a: Color, b: Color, amount: f32) -> Color {
+    let channel = |x: u8, y: u8| -> u8 {
+        linear_to_srgb(srgb_to_linear(x)*amount + srgb_to_linear(y)*(1.0 - amount))
+    };
+    Color { r: channel(a.r, b.r), g: channel(a.g, b.g), b: channel(a.b, b.b), a: 255 }
+}
+
+// The first few colors of the terminal's palette, using the real colors
+// where we know them and the defaults from ansi_palette() otherwise. If the
+// user's palette has fewer colors than asked for, only those are used.
+fn basic_palette(opts: &RenderOptions, count: usize) -> Vec<Color> {
+    let mut palette = ansi_palette();
+    palette.truncate(count.min(opts.basic_colors));
+    for (color, known) in palette.iter_mut().zip(&opts.term_colors) {
+        if let Some(c) = *known {
+            *color = c;
+        }
+    }
+    palette
+}
+
+// The palette of color values for the 16 basic terminal colors. In terms of
+// data types, it's an array of exoquant::Color structs. These values the
+// values used in xterm (According to https://jonasjacek.github.io/colors/ )
+// but are a reasonable approximation for terminals in general.
+fn ansi_palette() -> Vec<Color> {
+    vec![
         Color { r: 0, g: 0, b: 0, a: 255 },
         Color { r: 128, g: 0, b: 0, a: 255 },
         Color { r: 0, g: 128, b: 0, a: 255 },
@@ -401,67 +1373,54 @@ fn render_16colors(img: image::DynamicImage, dither: &str) {
         Color { r: 255, g: 0, b: 255, a: 255 },
         Color { r: 0, g: 255, b: 255, a: 255 },
         Color { r: 255, g: 255, b: 255, a: 255 },
-    ];
-
-    let (width, height) = img.dimensions();
-    
-    let indexed_data = quantize(img, &palette, dither);
-
-    for y in 0..height {
-        for x in 0..width {
-            let pixel_color = indexed_data[(width*y + x) as usize];
-            match pixel_color {
-                0 => print!("{} ", color::Bg(color::Black)),
-                1 => print!("{} ", color::Bg(color::Red)),
-                2 => print!("{} ", color::Bg(color::Green)),
-                3 => print!("{} ", color::Bg(color::Yellow)),
-                4 => print!("{} ", color::Bg(color::Blue)),
-                5 => print!("{} ", color::Bg(color::Magenta)),
-                6 => print!("{} ", color::Bg(color::Cyan)),
-                7 => print!("{} ", color::Bg(color::White)),
-                8 => print!("{} ", color::Bg(color::LightBlack)),
-                9 => print!("{} ", color::Bg(color::LightRed)),
-                10 => print!("{} ", color::Bg(color::LightGreen)),
-                11 => print!("{} ", color::Bg(color::LightYellow)),
-                12 => print!("{} ", color::Bg(color::LightBlue)),
-                13 => print!("{} ", color::Bg(color::LightMagenta)),
-                14 => print!("{} ", color::Bg(color::LightCyan)),
-                15 => print!("{} ", color::Bg(color::LightWhite)),
-                // We _should_ only get colors 0 through 15 but a little
-                // defensive programming never hurts.
-                _ => print!("{} ", color::Bg(color::White)),
-            }
-        }
-        // Reset colors at the end of each line. If we don't do this, the
-        // color of the rightmost pixel in each line is extended to the right
-        // edge of the screen.
-        println!("{}", color::Bg(color::Reset));
-    }
+    ]
 }
 
 // Display images using 256 colors. Note that not all terminals can do this.
-fn render_256colors(img: image::DynamicImage, dither: &str) {
-    let palette = generate_256colors_palette();
+fn render_256colors(img: image::DynamicImage, dither: &str, linear: bool, term_colors: &[Option<Color>]) -> grid::Grid {
+    let (palette, indexes) = palette_256colors(term_colors);
 
     let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
 
-    let indexed_data = quantize(img, &palette, dither);
+    let indexed_data = quantize(img, &palette, dither, linear);
 
     for y in 0..height {
         for x in 0..width {
-            let pixel_color = indexed_data[(width*y + x) as usize] + 16;
-            print!("{} ", color::Bg(color::AnsiValue(pixel_color)));
+            let pixel_color = indexes[indexed_data[(width*y + x) as usize]];
+            grid.set(x, y, grid::Cell::block(grid::Ink::Indexed(pixel_color)));
         }
-        // Reset colors at the end of each line. If we don't do this, the
-        // color of the rightmost pixel in each line is extended to the right
-        // edge of the screen.
-        println!("{}", color::Bg(color::Reset));
     }
+
+    grid
+}
+
+// Work out which colors the 256 color mode can use, along with the
+// terminal's palette index for each one. Colors we know the real value of are
+// used as they are. Otherwise the first 16 are left out because they vary
+// from terminal to terminal, and the rest get their standard values.
+fn palette_256colors(term_colors: &[Option<Color>]) -> (Vec<Color>, Vec<u8>) {
+    let standard = generate_256colors_palette();
+
+    let mut palette = Vec::new();
+    let mut indexes = Vec::new();
+    for i in 0..256 {
+        let color = match term_colors.get(i).cloned().unwrap_or(None) {
+            Some(c) => c,
+            None if i >= 16 => standard[i - 16],
+            None => continue,
+        };
+        palette.push(color);
+        indexes.push(i as u8);
+    }
+
+    (palette, indexes)
 }
 
 // Generate the palette of colors used for the 256 color mode. We could
 // hardcode this but it also makes sense to generate it because the palette is
 // large but fairly regular.
+#[allow(clippy::needless_return, clippy::useless_vec)]
 fn generate_256colors_palette() -> Vec<Color> {
     // Don't put the first 16 colors in the palette because they vary from
     // terminal to terminal.
@@ -494,8 +1453,9 @@ fn generate_256colors_palette() -> Vec<Color> {
 }
 
 // Render an image in truecolor, i.e. 24-bit color.
-fn render_truecolor(img: image::DynamicImage) {
+fn render_truecolor(img: image::DynamicImage) -> grid::Grid {
     let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
 
     for y in 0..height {
         for x in 0..width {
@@ -504,66 +1464,133 @@ fn render_truecolor(img: image::DynamicImage) {
             let red = pixel_channels[0];
             let green = pixel_channels[1];
             let blue = pixel_channels[2];
-            print!("{} ", color::Bg(color::Rgb(red, green, blue)));
+            grid.set(x, y, grid::Cell::block(grid::Ink::Rgb(red, green, blue)));
         }
-        // Reset colors at the end of each line. If we don't do this, the
-        // color of the rightmost pixel in each line is extended to the right
-        // edge of the screen.
-        println!("{}", color::Bg(color::Reset));        
     }
+
+    grid
+}
+
+// Render an image in truecolor, but only using the colors of a palette. This
+// is for drawing with a fixed set of colors, like a Game Boy's.
+fn render_palette(img: image::DynamicImage, dither: &str, linear: bool, palette: &[Color]) -> grid::Grid {
+    let (width, height) = img.dimensions();
+    let mut grid = grid::Grid::new(width, height);
+
+    let indexed_data = quantize(img, palette, dither, linear);
+
+    for y in 0..height {
+        for x in 0..width {
+            let c = palette[indexed_data[(width*y + x) as usize]];
+            grid.set(x, y, grid::Cell::block(grid::Ink::Rgb(c.r, c.g, c.b)));
+        }
+    }
+
+    grid
 }
 
 // Quantize an image given the image, the palette, and the dithering mode. This
-// code uses the exoquant library for quantization.
-fn quantize(img: image::DynamicImage, palette: &[Color], dither: &str) -> Vec<u8> {
+// code uses the exoquant library for quantization. The indexes are usize so
+// that palettes can have more than 256 colors.
+#[allow(clippy::needless_return, clippy::needless_late_init, clippy::needless_borrow)]
+fn quantize(img: image::DynamicImage, palette: &[Color], dither: &str, linear: bool) -> Vec<usize> {
     let (width, _) = img.dimensions();
 
     // Convert image into a format exoquant can understand.
     let img_vec = image_to_exoquant(img);
 
     let indexed_data;
-    let colorspace = SimpleColorSpace::default();
+    let colorspace = DitherColorSpace { linear, simple: SimpleColorSpace::default() };
     // This match statement is messy because doing it the simplest way
     // (changing the ditherer variable only) causes type errors.
     match dither {
         "none" => {
             let ditherer = ditherer::None;
             let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            indexed_data = remapper.remap(&img_vec, width as usize);            
+            indexed_data = remapper.remap_usize(&img_vec, width as usize);            
         }
         "fs" => {
             let ditherer = ditherer::FloydSteinberg::new();
             let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            indexed_data = remapper.remap(&img_vec, width as usize);            
+            indexed_data = remapper.remap_usize(&img_vec, width as usize);            
 
         }
         "fs-vanilla" => {
             let ditherer = ditherer::FloydSteinberg::vanilla();
             let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            indexed_data = remapper.remap(&img_vec, width as usize);            
+            indexed_data = remapper.remap_usize(&img_vec, width as usize);            
         }
         "fs-checkered" => {
             let ditherer = ditherer::FloydSteinberg::checkered();
             let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            indexed_data = remapper.remap(&img_vec, width as usize);            
+            indexed_data = remapper.remap_usize(&img_vec, width as usize);            
         }
         "ordered" => {
             let ditherer = ditherer::Ordered;
             let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            indexed_data = remapper.remap(&img_vec, width as usize);            
+            indexed_data = remapper.remap_usize(&img_vec, width as usize);            
         }
         // We should never reach this unless there is a bug.
         _ => {
             let ditherer = ditherer::None;
             let remapper = Remapper::new(&palette, &colorspace, &ditherer);
-            indexed_data = remapper.remap(&img_vec, width as usize);            
+            indexed_data = remapper.remap_usize(&img_vec, width as usize);            
         }
     }
     return indexed_data;    
 } 
 
+// The color space we hand to exoquant. Colors are matched exactly like in
+// exoquant's own color space, but when linear is set the dithering error is
+// spread around in real linear light. exoquant's default only gets roughly
+// there with a plain power curve, which makes dithered gradients uneven.
+struct DitherColorSpace {
+    linear: bool,
+    simple: SimpleColorSpace,
+}
+
+impl ColorSpace for DitherColorSpace {
+    fn to_linear(&self, color: Colorf) -> Colorf {
+        self.simple.to_linear(color)
+    }
+
+    fn from_linear(&self, color: Colorf) -> Colorf {
+        self.simple.from_linear(color)
+    }
+
+    fn to_dither(&self, color: Colorf) -> Colorf {
+        if !self.linear {
+            return self.simple.to_dither(color);
+        }
+        let c = self.simple.from_linear(color);
+        Colorf { r: decode_srgb(c.r), g: decode_srgb(c.g), b: decode_srgb(c.b), a: c.a }
+    }
+
+    fn from_dither(&self, color: Colorf) -> Colorf {
+        if !self.linear {
+            return self.simple.from_dither(color);
+        }
+        let c = Colorf { r: encode_srgb(color.r), g: encode_srgb(color.g), b: encode_srgb(color.b), a: color.a };
+        self.simple.to_linear(c)
+    }
+}
+
+// Count the different colors in an image, giving up once there are at least
+// limit of them.
+fn count_colors(img: &image::DynamicImage, limit: usize) -> usize {
+    let mut seen = HashSet::new();
+    for (_, _, pixel) in img.pixels() {
+        seen.insert(pixel.data);
+        if seen.len() >= limit {
+            break;
+        }
+    }
+    seen.len()
+}
+
 // Convert an image from the image libary's format into the format exoquant
 // uses.
+#[allow(clippy::needless_return)]
 fn image_to_exoquant(input: image::DynamicImage) -> Vec<Color> {
     let (width, height) = input.dimensions();
     let mut img_vec: Vec<Color> = Vec::new();
@@ -579,6 +1606,36 @@ fn image_to_exoquant(input: image::DynamicImage) -> Vec<Color> {
     return img_vec;
 }
 
+// Convert an sRGB color channel into linear light, from 0.0 to 1.0.
+fn srgb_to_linear(value: u8) -> f32 {
+    decode_srgb(value as f64 / 255.0) as f32
+}
+
+// Convert linear light back into an sRGB color channel.
+fn linear_to_srgb(value: f32) -> u8 {
+    (encode_srgb(value as f64) * 255.0).round() as u8
+}
+
+// The sRGB curve, going from a color channel from 0.0 to 1.0 to the amount
+// of light, and back.
+fn decode_srgb(v: f64) -> f64 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn encode_srgb(v: f64) -> f64 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 // Debug code: Print the color values of the palette.
 //fn print_palette(palette: &Vec<Color>) {
 //    for i in 0..palette.len() {
@@ -586,3 +1643,55 @@ fn image_to_exoquant(input: image::DynamicImage) -> Vec<Color> {
 //        println!("{} {} {} {}", color.r, color.g, color.b, color.a);
 //    }
 //}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A checkerboard of black and white pixels, which looks 50% grey from
+    // far enough away. In linear light that's 188, not 128.
+    fn checker(size: u32) -> image::DynamicImage {
+        let img = ImageBuffer::from_fn(size, size, |x, y| {
+            let v = if (x + y) % 2 == 0 { 0 } else { 255 };
+            Rgba { data: [v, v, v, 255] }
+        });
+        image::ImageRgba8(img)
+    }
+
+    fn grey(img: &image::DynamicImage) -> u8 {
+        img.to_rgba().get_pixel(0, 0).data[0]
+    }
+
+    #[test]
+    fn box_resize_mixes_in_linear_light() {
+        let img = checker(8);
+        assert!((186..=190).contains(&grey(&resample(&img, 1, 1, "box", true))));
+        assert!((126..=130).contains(&grey(&resample(&img, 1, 1, "box", false))));
+    }
+
+    #[test]
+    fn filtered_resize_mixes_in_linear_light() {
+        let img = checker(8);
+        assert!((186..=190).contains(&grey(&resample(&img, 1, 1, "triangle", true))));
+        assert!((126..=130).contains(&grey(&resample(&img, 1, 1, "triangle", false))));
+    }
+
+    // The fraction of the pixels that come out white when a flat grey is
+    // dithered to black and white.
+    fn dithered_white(v: u8) -> f32 {
+        let img = image::ImageRgba8(ImageBuffer::from_pixel(32, 32, Rgba { data: [v, v, v, 255] }));
+        let palette = [Color { r: 0, g: 0, b: 0, a: 255 }, Color { r: 255, g: 255, b: 255, a: 255 }];
+        let indexes = quantize(img, &palette, "fs", true);
+        indexes.iter().filter(|&&i| i == 1).count() as f32 / indexes.len() as f32
+    }
+
+    #[test]
+    fn dithering_spreads_error_in_linear_light() {
+        // 188 is half as bright as white in linear light, and 128 is only
+        // about a fifth. Spread in sRGB values they'd be 74% and 50%.
+        let light = dithered_white(188);
+        let dark = dithered_white(128);
+        assert!(light > 0.45 && light < 0.65, "{} of the pixels are white", light);
+        assert!(dark > 0.15 && dark < 0.3, "{} of the pixels are white", dark);
+    }
+}
@@ -0,0 +1,334 @@
+// Loading color palettes from files, so river knows what colors the
+// terminal really shows instead of guessing. Most terminal themes can be
+// read as they are: GIMP palettes, Xresources, alacritty, kitty and Windows
+// Terminal color schemes, and plain lists of hex colors. There are also a
+// few built-in palettes from old computers and consoles.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use exoquant::Color;
+
+// The built-in palettes. The CGA one is in terminal order (red before blue)
+// so it also works as a terminal theme.
+const BUILTIN: [(&str, &[u32]); 3] = [
+    ("cga", &[0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa,
+              0x555555, 0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff]),
+    ("gameboy", &[0x0f380f, 0x306230, 0x8bac0f, 0x9bbc0f]),
+    ("pico8", &[0x000000, 0x1d2b53, 0x7e2553, 0x008751, 0xab5236, 0x5f574f, 0xc2c3c7, 0xfff1e8,
+                0xff004d, 0xffa300, 0xffec27, 0x00e436, 0x29adff, 0x83769c, 0xff77a8, 0xffccaa]),
+];
+
+// The names themes use for the eight basic colors, in terminal order.
+const COLOR_NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+// Load a palette from a file, or one of the built-in palettes by name. The
+// result is indexed like the terminal's palette, with None for the colors
+// the file doesn't mention.
+pub fn load(name: &str) -> Result<Vec<Option<Color>>, String> {
+    if !Path::new(name).exists() {
+        for &(builtin, values) in BUILTIN.iter() {
+            if builtin == name {
+                return Ok(values.iter().map(|&v| Some(from_u32(v))).collect());
+            }
+        }
+    }
+
+    let text = match fs::read_to_string(name) {
+        Ok(t) => t,
+        Err(e) => return Err(format!("Can't read palette file `{}': {}", name, e)),
+    };
+
+    let colors = if text.starts_with("GIMP Palette") || name.ends_with(".gpl") {
+        parse_gpl(&text)
+    } else if let Some(named) = parse_named(&text) {
+        named
+    } else {
+        parse_list(&text)?
+    };
+
+    if colors.iter().all(|c| c.is_none()) {
+        return Err(format!("There are no colors in `{}'.", name));
+    }
+    Ok(colors)
+}
+
+// A GIMP palette has a header and then one color per line as three decimal
+// numbers, optionally followed by a name.
+fn parse_gpl(text: &str) -> Vec<Option<Color>> {
+    let mut colors = Vec::new();
+    for line in text.lines() {
+        let numbers: Vec<u8> = line.split_whitespace()
+            .take(3)
+            .map_while(|s| s.parse::<u8>().ok())
+            .collect();
+        if numbers.len() == 3 {
+            colors.push(Some(Color { r: numbers[0], g: numbers[1], b: numbers[2], a: 255 }));
+        }
+    }
+    colors
+}
+
+// A plain list of hex colors, one per line, in palette order. Blank lines
+// and lines starting with "# ", "//" or "!" are comments.
+fn parse_list(text: &str) -> Result<Vec<Option<Color>>, String> {
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("# ") || line.starts_with("//") || line.starts_with('!') {
+            continue;
+        }
+        match parse_hex(line.split_whitespace().next().unwrap_or("")) {
+            Some(c) => colors.push(Some(c)),
+            None => return Err(format!("Line {} isn't a color: {}", number + 1, line)),
+        }
+    }
+    Ok(colors)
+}
+
+// Read a theme that gives its colors names, which covers Xresources
+// (*color4: #...), kitty (color4 #...), alacritty (blue = '#...' under
+// [colors.normal]) and Windows Terminal ("brightBlue": "#..."). Returns
+// None if the text doesn't look like one of those.
+fn parse_named(text: &str) -> Option<Vec<Option<Color>>> {
+    let mut colors: Vec<Option<Color>> = Vec::new();
+    let mut found = false;
+    // Xresources themes often #define the colors first and use them later.
+    let mut defines: HashMap<String, Color> = HashMap::new();
+    // alacritty keeps the bright colors in their own section, and has other
+    // sections like dim and primary that we don't want.
+    let mut section = "normal";
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') {
+            continue;
+        }
+
+        let words: Vec<&str> = line
+            .split(|c: char| c.is_whitespace() || c == ':' || c == '=' || c == ',' || c == '"' || c == '\'')
+            .filter(|w| !w.is_empty())
+            .collect();
+        if words.is_empty() {
+            continue;
+        }
+
+        if words[0] == "#define" && words.len() >= 3 {
+            if let Some(c) = parse_hex(words[2]) {
+                defines.insert(words[1].to_string(), c);
+            }
+            continue;
+        }
+
+        // A line that names a section without giving a color, like
+        // [colors.bright] in TOML or bright: in YAML. JSON keys are quoted
+        // and its brackets are on their own, so a lone [ or "schemes": [
+        // doesn't count.
+        let is_name = |w: &str| !w.is_empty() && w.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '-');
+        let toml = line.starts_with('[') && line.ends_with(']') && is_name(line.trim_matches(|c| c == '[' || c == ']'));
+        let yaml = words.len() == 1 && !line.starts_with('"') && is_name(words[0]);
+        if toml || yaml {
+            let header = line.to_lowercase();
+            section = if header.contains("bright") {
+                "bright"
+            } else if header.contains("normal") {
+                "normal"
+            } else {
+                "other"
+            };
+            continue;
+        }
+        if words.len() == 1 {
+            continue;
+        }
+
+        let key = words[0].trim_start_matches('*').trim_start_matches('.').to_lowercase();
+        // Xresources keys can have a program in front, as in URxvt.color4.
+        let key = key.rsplit('.').next().unwrap_or("").to_string();
+        let value = words[1..].iter()
+            .filter_map(|w| parse_hex(w).or_else(|| defines.get(*w).cloned()))
+            .next();
+
+        let index = match key.strip_prefix("color") {
+            Some(number) if !number.is_empty() => number.parse::<usize>().ok(),
+            _ => named_index(&key, section),
+        };
+
+        if let (Some(i), Some(c)) = (index, value) {
+            if i < 256 {
+                if colors.len() <= i {
+                    colors.resize(i + 1, None);
+                }
+                colors[i] = Some(c);
+                found = true;
+            }
+        }
+    }
+
+    if found {
+        Some(colors)
+    } else {
+        None
+    }
+}
+
+// Turn a color name like "blue", "brightBlue" or "bright_blue" into its
+// palette index. Windows Terminal says purple where the others say magenta.
+fn named_index(key: &str, section: &str) -> Option<usize> {
+    if section == "other" {
+        return None;
+    }
+    let key = key.replace('_', "").replace("purple", "magenta");
+    let (name, bright) = if let Some(name) = key.strip_prefix("bright") {
+        (name, true)
+    } else if let Some(name) = key.strip_prefix("light") {
+        (name, true)
+    } else {
+        (&key[..], section == "bright")
+    };
+    let base = COLOR_NAMES.iter().position(|&n| n == name)?;
+    Some(if bright { base + 8 } else { base })
+}
+
+// Parse a color written as hex, with or without a leading # or 0x. Both the
+// six digit form and the three digit shorthand work.
+pub fn parse_hex(text: &str) -> Option<Color> {
+    let hex = text.trim_start_matches('#').trim_start_matches("0x");
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        6 => Some(from_u32(u32::from_str_radix(hex, 16).ok()?)),
+        3 => {
+            let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+            Some(Color { r: digit(0)*17, g: digit(1)*17, b: digit(2)*17, a: 255 })
+        },
+        _ => None,
+    }
+}
+
+fn from_u32(value: u32) -> Color {
+    Color { r: (value >> 16) as u8, g: (value >> 8) as u8, b: value as u8, a: 255 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The colors as hex, with - for the ones that weren't given.
+    fn hex(colors: &[Option<Color>]) -> Vec<String> {
+        colors.iter()
+            .map(|c| match *c {
+                Some(c) => format!("{:02x}{:02x}{:02x}", c.r, c.g, c.b),
+                None => "-".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reads_gimp_palettes() {
+        let text = "GIMP Palette\nName: Test\nColumns: 2\n#\n  0   0   0\tBlack\n255 128  64 Orange\n 16  32  48\n";
+        assert_eq!(hex(&parse_gpl(text)), vec!["000000", "ff8040", "102030"]);
+    }
+
+    #[test]
+    fn reads_hex_lists() {
+        let text = "# A comment\n\n#000000\nff8040 orange\n// Another comment\n0x102030\n! And another\n#fff\n";
+        assert_eq!(hex(&parse_list(text).unwrap()), vec!["000000", "ff8040", "102030", "ffffff"]);
+        assert!(parse_list("#000000\nnope\n").is_err());
+    }
+
+    #[test]
+    fn reads_xresources() {
+        let text = "! Solarized\n#define S_base03 #002b36\n#define S_red #dc322f\n\
+            *background: S_base03\n*color0: S_base03\n*.color1: S_red\nURxvt.color9:   #cb4b16\n";
+        assert_eq!(hex(&parse_named(text).unwrap()), vec!["002b36", "dc322f", "-", "-", "-", "-", "-", "-", "-", "cb4b16"]);
+    }
+
+    #[test]
+    fn reads_kitty_themes() {
+        let text = "# kitty\nforeground #c5c8c6\nbackground #1d1f21\ncolor0 #1d1f21\ncolor2 #b5bd68\ncolor15 #ffffff\n";
+        let colors = parse_named(text).unwrap();
+        assert_eq!(colors.len(), 16);
+        assert_eq!(hex(&colors[..3]), vec!["1d1f21", "-", "b5bd68"]);
+        assert_eq!(hex(&colors[15..]), vec!["ffffff"]);
+    }
+
+    #[test]
+    fn reads_alacritty_themes() {
+        let text = "[colors.primary]\nbackground = '#1d1f21'\nforeground = '#c5c8c6'\n\n\
+            [colors.normal]\nblack = '#000000'\nred = \"#cc6666\"\n\n\
+            [colors.bright]\nblack = '#666666'\nred = '#d54e53'\n\n\
+            [colors.dim]\nblack = '#131415'\n";
+        assert_eq!(hex(&parse_named(text).unwrap()), vec!["000000", "cc6666", "-", "-", "-", "-", "-", "-", "666666", "d54e53"]);
+    }
+
+    #[test]
+    fn reads_windows_terminal_schemes() {
+        let text = r##"{
+    "schemes":
+    [
+        {
+            "name": "Campbell",
+            "background": "#0C0C0C",
+            "black": "#0C0C0C",
+            "purple": "#881798",
+            "brightBlack": "#767676",
+            "brightPurple": "#B4009E"
+        }
+    ]
+}"##;
+        let colors = parse_named(text).unwrap();
+        assert_eq!(colors.len(), 14);
+        assert_eq!(hex(&colors[..1]), vec!["0c0c0c"]);
+        assert_eq!(hex(&colors[5..6]), vec!["881798"]);
+        assert_eq!(hex(&colors[8..]), vec!["767676", "-", "-", "-", "-", "b4009e"]);
+        // Even with the opening bracket on the same line.
+        let colors = parse_named("{\"schemes\": [{\n\"red\": \"#ff0000\"\n}]}").unwrap();
+        assert_eq!(hex(&colors), vec!["-", "ff0000"]);
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(hex(&[parse_hex("#ff8040"), parse_hex("0x102030"), parse_hex("abc"), parse_hex("#FfFfFf")]),
+            vec!["ff8040", "102030", "aabbcc", "ffffff"]);
+        assert!(parse_hex("#ff804").is_none());
+        assert!(parse_hex("#gg0000").is_none());
+        assert!(parse_hex("").is_none());
+    }
+
+    #[test]
+    fn loads_files_and_builtins() {
+        assert_eq!(hex(&load("gameboy").unwrap()), vec!["0f380f", "306230", "8bac0f", "9bbc0f"]);
+
+        let dir = ::std::env::temp_dir();
+        let gpl = dir.join(format!("river-test-{}.gpl", ::std::process::id()));
+        fs::write(&gpl, "GIMP Palette\n1 2 3\n").unwrap();
+        let list = dir.join(format!("river-test-{}.txt", ::std::process::id()));
+        fs::write(&list, "#010203\n").unwrap();
+        let empty = dir.join(format!("river-test-{}-empty.txt", ::std::process::id()));
+        fs::write(&empty, "// nothing\n").unwrap();
+
+        assert_eq!(hex(&load(gpl.to_str().unwrap()).unwrap()), vec!["010203"]);
+        assert_eq!(hex(&load(list.to_str().unwrap()).unwrap()), vec!["010203"]);
+        assert!(load(empty.to_str().unwrap()).is_err());
+        assert!(load("no-such-palette").is_err());
+        for path in [gpl, list, empty].iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
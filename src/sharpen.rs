@@ -0,0 +1,119 @@
+// Sharpening the image after it's been shrunk. Shrinking a photo down to a
+// terminal full of characters averages away the fine detail, so these
+// filters exaggerate what's left of it to keep faces and outlines
+// recognizable. The more the image was shrunk, the harder they have to work.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use image::{self, DynamicImage};
+
+// The names of the filters, for the argument parser.
+pub const FILTER_NAMES: [&str; 3] = ["unsharp", "edge", "local"];
+
+// Pick how strong the sharpening should be from how many times the image
+// was shrunk. Images that weren't shrunk don't need any, and the strength
+// goes up by a quarter each time the factor doubles.
+pub fn auto_amount(factor: f32) -> f32 {
+    if factor <= 1.0 {
+        return 0.0;
+    }
+    (factor.log2() * 0.25).min(1.5)
+}
+
+// Sharpen an image with one of the filters. They all work by blurring the
+// image and pushing every pixel away from its blurred value, and differ in
+// how much they blur:
+//
+// - unsharp uses a small Gaussian blur, for crisper detail.
+// - edge uses the average of the eight neighbors twice as hard, which brings
+//   out outlines more than texture.
+// - local uses a wide blur with a gentle push, so it raises the contrast
+//   between areas instead of between neighboring pixels.
+pub fn sharpen(img: DynamicImage, filter: &str, amount: f32) -> DynamicImage {
+    if amount <= 0.0 {
+        return img;
+    }
+    let img = img.to_rgba();
+    let (width, height) = img.dimensions();
+    let data: Vec<f32> = img.into_raw().into_iter().map(|v| v as f32).collect();
+
+    let (blurred, amount) = match filter {
+        "edge" => (neighbor_average(&data, width as usize, height as usize), amount * 2.0),
+        "local" => {
+            let radius = ((width.max(height) / 16) as usize).max(2);
+            let once = box_blur(&data, width as usize, height as usize, radius);
+            (box_blur(&once, width as usize, height as usize, radius), amount * 0.5)
+        },
+        _ => (gaussian_blur(&data, width as usize, height as usize), amount),
+    };
+
+    let mut out = Vec::with_capacity(data.len());
+    for (i, (&v, &b)) in data.iter().zip(blurred.iter()).enumerate() {
+        if i % 4 == 3 {
+            out.push(v as u8);
+        } else {
+            out.push((v + (v - b) * amount).round().clamp(0.0, 255.0) as u8);
+        }
+    }
+    image::ImageRgba8(image::ImageBuffer::from_raw(width, height, out).unwrap())
+}
+
+// Blur an RGBA image of floats with the 1 2 1 kernel in both directions.
+fn gaussian_blur(data: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let horizontal = convolve(data, width, height, &[1.0, 2.0, 1.0], true);
+    convolve(&horizontal, width, height, &[1.0, 2.0, 1.0], false)
+}
+
+// Replace every pixel with the average of the eight around it.
+fn neighbor_average(data: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let horizontal = convolve(data, width, height, &[1.0, 1.0, 1.0], true);
+    let box3 = convolve(&horizontal, width, height, &[1.0, 1.0, 1.0], false);
+    // The 3x3 box includes the pixel itself, so take it back out.
+    box3.iter().zip(data.iter()).map(|(&b, &v)| (b*9.0 - v) / 8.0).collect()
+}
+
+// Average every pixel with the ones up to radius pixels away.
+fn box_blur(data: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    let kernel = vec![1.0; 2*radius + 1];
+    let horizontal = convolve(data, width, height, &kernel, true);
+    convolve(&horizontal, width, height, &kernel, false)
+}
+
+// Run a normalized one-dimensional kernel over an RGBA image of floats,
+// either across or down. Pixels past the edge are taken to be copies of the
+// edge pixels.
+fn convolve(data: &[f32], width: usize, height: usize, kernel: &[f32], horizontal: bool) -> Vec<f32> {
+    let total: f32 = kernel.iter().sum();
+    let reach = (kernel.len() / 2) as isize;
+    let mut out = vec![0.0; data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..4 {
+                let mut sum = 0.0;
+                for (k, &weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - reach;
+                    let (sx, sy) = if horizontal {
+                        ((x as isize + offset).clamp(0, width as isize - 1) as usize, y)
+                    } else {
+                        (x, (y as isize + offset).clamp(0, height as isize - 1) as usize)
+                    };
+                    sum += data[(sy*width + sx)*4 + c] * weight;
+                }
+                out[(y*width + x)*4 + c] = sum / total;
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,222 @@
+// Talking to the terminal. Many terminals will tell you about themselves if
+// you send them the right escape code, so this is where we ask questions and
+// read the answers.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+use libc;
+use termion;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+
+// How long to wait for the terminal to answer, in milliseconds. Terminals
+// answer in a few milliseconds, this is mostly for slow SSH connections.
+const TIMEOUT: u64 = 500;
+
+// Ask the terminal a question and return its answer, or None if it doesn't
+// answer or the output isn't a terminal. The question is followed by a
+// request for the terminal's device attributes, which practically every
+// terminal answers. When that answer shows up we know the terminal has said
+// all it's going to say, so we don't have to wait for the timeout on
+// terminals that ignore the question.
+pub fn query(question: &str) -> Option<String> {
+    if !termion::is_tty(&io::stdout()) {
+        return None;
+    }
+    let mut tty = termion::get_tty().ok()?;
+    // Raw mode stops the answer from being echoed on the screen. It is
+    // switched off again when this goes out of scope.
+    let _raw = io::stdout().into_raw_mode().ok()?;
+
+    write!(tty, "{}\x1b[c", question).ok()?;
+    tty.flush().ok()?;
+
+    let deadline = Instant::now() + Duration::from_millis(TIMEOUT);
+    let mut reply: Vec<u8> = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        let wait = deadline - now;
+
+        // Wait for something to read, so we never block past the deadline.
+        let mut fds = libc::pollfd { fd: tty.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ready = unsafe { libc::poll(&mut fds, 1, wait.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            return None;
+        }
+        let n = tty.read(&mut buf).ok()?;
+        if n == 0 {
+            return None;
+        }
+        reply.extend_from_slice(&buf[..n]);
+
+        // The device attributes look like ESC [ ? 6 2 ; 2 2 c.
+        if let Some(start) = find(&reply, b"\x1b[?") {
+            if reply.ends_with(b"c") {
+                reply.truncate(start);
+                return Some(String::from_utf8_lossy(&reply).into_owned());
+            }
+        }
+    }
+}
+
+// Find the last place a byte string shows up in another one.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).rev().find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+// Work out whether the terminal has a dark background, or None if we can't
+// tell. Some terminals put their colors in the COLORFGBG variable, otherwise
+// we ask the terminal with OSC 11, unless the user doesn't want us to.
+pub fn background_is_dark(ask: bool) -> Option<bool> {
+    // Only the terminal we're printing to matters.
+    if !termion::is_tty(&io::stdout()) {
+        return None;
+    }
+
+    // COLORFGBG looks like "15;0" or "15;default;0", with the background
+    // color last. Following rxvt, colors 0 to 6 and 8 are the dark ones.
+    if let Ok(colorfgbg) = env::var("COLORFGBG") {
+        if let Some(Ok(bg)) = colorfgbg.rsplit(';').next().map(|s| s.parse::<u8>()) {
+            return Some(!matches!(bg, 7 | 9..=15));
+        }
+    }
+
+    if !ask {
+        return None;
+    }
+    let (r, g, b) = parse_color(&query("\x1b]11;?\x07")?)?;
+    let luma = 0.299*(r as f32) + 0.587*(g as f32) + 0.114*(b as f32);
+    Some(luma < 128.0)
+}
+
+// Work out the aspect ratio (width divided by height) of the terminal's
+// character cells from their size in pixels, or None if we can't tell. The
+// kernel knows the size of the window in pixels in many terminals, otherwise
+// we ask the terminal with CSI 16 t, unless the user doesn't want us to.
+pub fn cell_aspect_ratio(ask: bool) -> Option<f32> {
+    if !termion::is_tty(&io::stdout()) {
+        return None;
+    }
+
+    if let (Ok((columns, rows)), Ok((width, height))) = (termion::terminal_size(), termion::terminal_size_pixels()) {
+        if columns > 0 && rows > 0 && width > 0 && height > 0 {
+            return Some((width as f32 / columns as f32) / (height as f32 / rows as f32));
+        }
+    }
+
+    if !ask {
+        return None;
+    }
+    // The answer looks like ESC [ 6 ; height ; width t.
+    let reply = query("\x1b[16t")?;
+    let start = reply.find("\x1b[6;")? + 4;
+    let end = start + reply[start..].find('t')?;
+    let mut numbers = reply[start..end].split(';').map(|s| s.parse::<f32>().ok());
+    let height = numbers.next()??;
+    let width = numbers.next()??;
+    if width <= 0.0 || height <= 0.0 {
+        return None;
+    }
+    Some(width / height)
+}
+
+// Pull the color out of an answer like ESC ] 11 ; rgb:ffff/ffff/dddd BEL.
+// Each channel can have one to four hex digits.
+pub fn parse_color(reply: &str) -> Option<(u8, u8, u8)> {
+    let start = reply.find("rgb:")? + 4;
+    let end = reply[start..]
+        .find(|c: char| !c.is_ascii_hexdigit() && c != '/')
+        .map(|i| start + i)
+        .unwrap_or(reply.len());
+
+    let mut channels = Vec::new();
+    for part in reply[start..end].split('/') {
+        if part.is_empty() || part.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(part, 16).ok()?;
+        let max = (1u32 << (4*part.len())) - 1;
+        channels.push(((value*255 + max/2) / max) as u8);
+    }
+    if channels.len() != 3 {
+        return None;
+    }
+    Some((channels[0], channels[1], channels[2]))
+}
+
+// Ask the terminal what all 256 of its palette colors look like, using
+// OSC 4. The colors it doesn't tell us about are None. All the questions go
+// out at once so this only takes one round trip.
+pub fn palette_colors() -> Vec<Option<(u8, u8, u8)>> {
+    let mut colors = vec![None; 256];
+
+    let mut question = String::new();
+    for i in 0..256 {
+        question.push_str(&format!("\x1b]4;{};?\x07", i));
+    }
+    let reply = match query(&question) {
+        Some(r) => r,
+        None => return colors,
+    };
+
+    // Each answer looks like ESC ] 4 ; index ; rgb:rrrr/gggg/bbbb BEL.
+    for answer in reply.split("\x1b]4;").skip(1) {
+        let mut parts = answer.splitn(2, ';');
+        let index = parts.next().and_then(|s| s.parse::<usize>().ok());
+        let color = parts.next().and_then(parse_color);
+        if let (Some(i), Some(c)) = (index, color) {
+            if i < colors.len() {
+                colors[i] = Some(c);
+            }
+        }
+    }
+
+    colors
+}
+
+// The escape code that changes one of the terminal's palette colors.
+pub fn set_palette_color(index: u8, r: u8, g: u8, b: u8) -> String {
+    format!("\x1b]4;{};rgb:{:02x}/{:02x}/{:02x}\x07", index, r, g, b)
+}
+
+// The escape code that puts one of the terminal's palette colors back to
+// what it was before anyone changed it.
+pub fn reset_palette_color(index: u8) -> String {
+    format!("\x1b]104;{}\x07", index)
+}
+
+// Wait until the user presses a key. Does nothing if there's no terminal to
+// read from.
+pub fn wait_for_key() {
+    if !termion::is_tty(&io::stdout()) {
+        return;
+    }
+    if let Ok(tty) = termion::get_tty() {
+        if let Ok(_raw) = io::stdout().into_raw_mode() {
+            let _ = tty.keys().next();
+        }
+    }
+}
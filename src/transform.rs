@@ -0,0 +1,295 @@
+// Changes to the whole image that happen before it's resized: cropping,
+// rotating and flipping, plus turning photos the right way up using the
+// orientation the camera wrote into their EXIF data.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use image::{DynamicImage, GenericImage};
+
+// Read a crop rectangle written as x,y,w,h. Each number is in pixels, or in
+// percent of the image's width or height if it ends with %. The rectangle
+// is cut down to fit inside the image.
+pub fn parse_crop(spec: &str, width: u32, height: u32) -> Result<(u32, u32, u32, u32), String> {
+    let parts: Vec<&str> = spec.split(',').map(|s| s.trim()).collect();
+    if parts.len() != 4 {
+        return Err(format!("The crop `{}' should be four numbers: x,y,width,height.", spec));
+    }
+
+    let mut numbers = [0u32; 4];
+    for (i, part) in parts.iter().enumerate() {
+        let size = if i % 2 == 0 { width } else { height };
+        let value = match part.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok().map(|p| (p / 100.0 * size as f32).round()),
+            None => part.parse::<f32>().ok(),
+        };
+        numbers[i] = match value {
+            Some(v) if v >= 0.0 => v as u32,
+            _ => return Err(format!("`{}' isn't a valid crop size.", part)),
+        };
+    }
+
+    let x = numbers[0].min(width);
+    let y = numbers[1].min(height);
+    let w = numbers[2].min(width - x);
+    let h = numbers[3].min(height - y);
+    if w == 0 || h == 0 {
+        return Err(format!("The crop `{}' doesn't leave anything of the {}x{} image.", spec, width, height));
+    }
+    Ok((x, y, w, h))
+}
+
+// Cut a rectangle out of an image.
+pub fn crop(mut img: DynamicImage, rect: (u32, u32, u32, u32)) -> DynamicImage {
+    let (x, y, w, h) = rect;
+    let (width, height) = img.dimensions();
+    if x == 0 && y == 0 && w == width && h == height {
+        return img;
+    }
+    img.crop(x, y, w, h)
+}
+
+// Rotate an image clockwise by a number of degrees, which has to be 90, 180
+// or 270 (anything else leaves it alone).
+pub fn rotate(img: DynamicImage, degrees: u32) -> DynamicImage {
+    match degrees {
+        90 => img.rotate90(),
+        180 => img.rotate180(),
+        270 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// Mirror an image, "h" for left to right or "v" for top to bottom.
+pub fn flip(img: DynamicImage, direction: &str) -> DynamicImage {
+    match direction {
+        "h" => img.fliph(),
+        "v" => img.flipv(),
+        _ => img,
+    }
+}
+
+// Turn an image the right way up given its EXIF orientation, which says how
+// the camera was held. Orientations 5 and 7 are mirrored as well as turned.
+pub fn orient(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// Find the EXIF orientation of a JPEG file, or None if it doesn't have one.
+pub fn exif_orientation(path: &str) -> Option<u16> {
+    let file = File::open(path).ok()?;
+    jpeg_orientation(BufReader::new(file))
+}
+
+// The EXIF data lives in an APP1 segment near the start of a JPEG, and is
+// laid out like a little TIFF file. Only the segments up to it are read, not
+// the whole picture.
+fn jpeg_orientation<R: Read>(mut reader: R) -> Option<u16> {
+    let mut start = [0; 2];
+    reader.read_exact(&mut start).ok()?;
+    if start != [0xff, 0xd8] {
+        return None;
+    }
+
+    // Go through the segments at the start of the file. Each one is a marker
+    // and a big-endian length that includes the length itself.
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header).ok()?;
+        if header[0] != 0xff {
+            return None;
+        }
+        let marker = header[1];
+        let length = (((header[2] as usize) << 8) | header[3] as usize).checked_sub(2)?;
+        // The picture itself starts here, and there's no EXIF after that.
+        if marker == 0xda {
+            return None;
+        }
+        if marker == 0xe1 {
+            let mut segment = vec![0; length];
+            reader.read_exact(&mut segment).ok()?;
+            if segment.starts_with(b"Exif\0\0") {
+                return tiff_orientation(&segment[6..]);
+            }
+        } else if io::copy(&mut (&mut reader).take(length as u64), &mut io::sink()).ok()? < length as u64 {
+            return None;
+        }
+    }
+}
+
+// Read the orientation tag out of the first directory of a TIFF structure.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |i: usize| -> Option<u16> {
+        let b = tiff.get(i..i + 2)?;
+        Some(if little { u16::from(b[0]) | u16::from(b[1]) << 8 } else { u16::from(b[0]) << 8 | u16::from(b[1]) })
+    };
+    let u32_at = |i: usize| -> Option<u32> {
+        let (a, b) = (u32::from(u16_at(i)?), u32::from(u16_at(i + 2)?));
+        Some(if little { a | b << 16 } else { a << 16 | b })
+    };
+
+    let directory = u32_at(4)? as usize;
+    let entries = u16_at(directory)? as usize;
+    for i in 0..entries {
+        // Every entry is 12 bytes: tag, type, count and the value itself.
+        let entry = directory + 2 + 12*i;
+        if u16_at(entry)? == 0x0112 {
+            return u16_at(entry + 8);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Luma};
+
+    #[test]
+    fn parses_crops() {
+        assert_eq!(parse_crop("10,20,30,40", 100, 100), Ok((10, 20, 30, 40)));
+        assert_eq!(parse_crop(" 10 , 20 ,30, 40 ", 100, 100), Ok((10, 20, 30, 40)));
+        assert_eq!(parse_crop("10%,50%,50%,50%", 200, 100), Ok((20, 50, 100, 50)));
+        assert_eq!(parse_crop("0,0,100%,100%", 7, 3), Ok((0, 0, 7, 3)));
+        assert_eq!(parse_crop("1.6,0,2,2", 10, 10), Ok((1, 0, 2, 2)));
+    }
+
+    #[test]
+    fn cuts_crops_down_to_the_image() {
+        assert_eq!(parse_crop("90,0,50,50", 100, 100), Ok((90, 0, 10, 50)));
+        assert_eq!(parse_crop("0,0,1000,1000", 100, 50), Ok((0, 0, 100, 50)));
+        assert_eq!(parse_crop("50%,50%,200%,200%", 100, 50), Ok((50, 25, 50, 25)));
+        assert!(parse_crop("100,0,10,10", 100, 100).is_err());
+        assert!(parse_crop("0,500,10,10", 100, 100).is_err());
+        assert!(parse_crop("0,0,0,10", 100, 100).is_err());
+    }
+
+    #[test]
+    fn refuses_bad_crops() {
+        assert!(parse_crop("1,2,3", 100, 100).is_err());
+        assert!(parse_crop("1,2,3,4,5", 100, 100).is_err());
+        assert!(parse_crop("-1,0,10,10", 100, 100).is_err());
+        assert!(parse_crop("a,0,10,10", 100, 100).is_err());
+        assert!(parse_crop("0,0,10%%,10", 100, 100).is_err());
+        assert!(parse_crop("", 100, 100).is_err());
+    }
+
+    // A TIFF header and a first directory with a couple of entries, the
+    // orientation being the second one.
+    fn tiff(little: bool, orientation: u16) -> Vec<u8> {
+        let u16_bytes = |v: u16| if little { v.to_le_bytes() } else { v.to_be_bytes() };
+        let u32_bytes = |v: u32| if little { v.to_le_bytes() } else { v.to_be_bytes() };
+        let mut data = if little { b"II".to_vec() } else { b"MM".to_vec() };
+        data.extend_from_slice(&u16_bytes(42));
+        data.extend_from_slice(&u32_bytes(8));
+        data.extend_from_slice(&u16_bytes(2));
+        for &(tag, value) in [(0x010f, 7), (0x0112, orientation)].iter() {
+            data.extend_from_slice(&u16_bytes(tag));
+            data.extend_from_slice(&u16_bytes(3));
+            data.extend_from_slice(&u32_bytes(1));
+            data.extend_from_slice(&u16_bytes(value));
+            data.extend_from_slice(&[0, 0]);
+        }
+        data.extend_from_slice(&u32_bytes(0));
+        data
+    }
+
+    // A JPEG with some segments in it but no picture.
+    fn jpeg(segments: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8];
+        for &(marker, content) in segments {
+            data.extend_from_slice(&[0xff, marker]);
+            data.extend_from_slice(&(content.len() as u16 + 2).to_be_bytes());
+            data.extend_from_slice(content);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_both_tiff_byte_orders() {
+        for orientation in 1..9 {
+            assert_eq!(tiff_orientation(&tiff(true, orientation)), Some(orientation));
+            assert_eq!(tiff_orientation(&tiff(false, orientation)), Some(orientation));
+        }
+        assert_eq!(tiff_orientation(b"XX\0\x2a"), None);
+        assert_eq!(tiff_orientation(&tiff(true, 6)[..20]), None);
+        // A directory without the orientation tag.
+        let mut data = tiff(false, 6);
+        data[22] = 0x13;
+        assert_eq!(tiff_orientation(&data), None);
+    }
+
+    #[test]
+    fn finds_exif_in_jpegs() {
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend_from_slice(&tiff(false, 6));
+        let data = jpeg(&[(0xe0, b"JFIF\0\x01\x01"), (0xe1, b"http://ns.adobe.com/xap/1.0/\0"), (0xe1, &exif), (0xda, b"")]);
+        assert_eq!(jpeg_orientation(&data[..]), Some(6));
+
+        // EXIF after the picture starts doesn't count.
+        let data = jpeg(&[(0xe0, b"JFIF"), (0xda, b""), (0xe1, &exif)]);
+        assert_eq!(jpeg_orientation(&data[..]), None);
+        assert_eq!(jpeg_orientation(&jpeg(&[(0xe0, b"JFIF")])[..]), None);
+        assert_eq!(jpeg_orientation(&b"\x89PNG\r\n\x1a\n"[..]), None);
+        // A segment that says it's longer than the file.
+        assert_eq!(jpeg_orientation(&[0xff, 0xd8, 0xff, 0xe1, 0xff, 0xff, b'E'][..]), None);
+        assert_eq!(jpeg_orientation(&[0xff, 0xd8, 0xff, 0xe0, 0x00, 0x00][..]), None);
+    }
+
+    #[test]
+    fn turns_all_eight_orientations_upright() {
+        // A 3x2 picture with every pixel different.
+        let (w, h) = (3, 2);
+        let value = |x: u32, y: u32| (10*x + y + 1) as u8;
+        let stored = DynamicImage::ImageLuma8(ImageBuffer::from_fn(w, h, |x, y| Luma { data: [value(x, y)] }));
+
+        // Where each pixel of the upright picture comes from in the stored
+        // one, straight from the EXIF spec, and how big the upright one is.
+        type Source<'a> = &'a dyn Fn(u32, u32) -> (u32, u32);
+        let upright: [(u16, (u32, u32), Source); 8] = [
+            (1, (w, h), &|x, y| (x, y)),
+            (2, (w, h), &|x, y| (w - 1 - x, y)),
+            (3, (w, h), &|x, y| (w - 1 - x, h - 1 - y)),
+            (4, (w, h), &|x, y| (x, h - 1 - y)),
+            (5, (h, w), &|x, y| (y, x)),
+            (6, (h, w), &|x, y| (y, h - 1 - x)),
+            (7, (h, w), &|x, y| (w - 1 - y, h - 1 - x)),
+            (8, (h, w), &|x, y| (w - 1 - y, x)),
+        ];
+        for &(orientation, size, from) in upright.iter() {
+            let img = orient(stored.clone(), orientation).to_luma();
+            assert_eq!(img.dimensions(), size, "orientation {}", orientation);
+            for (x, y, p) in img.enumerate_pixels() {
+                let (sx, sy) = from(x, y);
+                assert_eq!(p.data[0], value(sx, sy), "orientation {} at {}, {}", orientation, x, y);
+            }
+        }
+    }
+}
@@ -0,0 +1,275 @@
+// Video from a pipe, as YUV4MPEG2 (what `ffmpeg -f yuv4mpegpipe` writes) or
+// as raw RGB frames. Both are just the pixels of every frame one after
+// another, so river can play video without knowing anything about codecs.
+
+// Copyright 2018 Thomas Szymczak
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{BufRead, BufReader, Read};
+use image::{DynamicImage, RgbImage};
+use animation::Frame;
+
+// The biggest video we'll play, which is more than 8K. A header asking for
+// more is broken, and believing it would mean setting aside gigabytes for
+// every frame.
+pub const MAX_SIZE: u32 = 8192;
+
+// A YUV4MPEG2 stream. It starts with a line saying how big the frames are
+// and how fast they go, then every frame is a line starting with FRAME
+// followed by the brightness of every pixel and then the two color planes,
+// which are often smaller than the picture.
+pub struct Y4m<R: Read> {
+    reader: BufReader<R>,
+    width: u32,
+    height: u32,
+    // How many pixels wide and tall each color sample is, or None for black
+    // and white video.
+    chroma: Option<(u32, u32)>,
+    // Whether the values go from 0 to 255, instead of 16 to 235 like they
+    // usually do in video.
+    full_range: bool,
+    // How long to show each frame for, in milliseconds.
+    delay: f64,
+}
+
+impl<R: Read> Y4m<R> {
+    // Read the header of a stream.
+    pub fn new(reader: R) -> Result<Y4m<R>, String> {
+        let mut reader = BufReader::new(reader);
+        let header = read_line(&mut reader).ok_or("The input is empty.")?;
+        let mut fields = header.split(' ');
+        if fields.next() != Some("YUV4MPEG2") {
+            return Err("The input isn't a YUV4MPEG2 stream.".to_string());
+        }
+
+        let mut video = Y4m { reader, width: 0, height: 0, chroma: Some((2, 2)), full_range: false, delay: 40.0 };
+        for field in fields.filter(|f| !f.is_empty()) {
+            let (tag, value) = field.split_at(1);
+            match tag {
+                "W" => video.width = value.parse().unwrap_or(0),
+                "H" => video.height = value.parse().unwrap_or(0),
+                "F" => {
+                    let mut parts = value.splitn(2, ':').map(|n| n.parse::<f64>());
+                    if let (Some(Ok(num)), Some(Ok(den))) = (parts.next(), parts.next()) {
+                        if num > 0.0 && den > 0.0 {
+                            video.delay = 1000.0 * den / num;
+                        }
+                    }
+                },
+                "C" => {
+                    video.chroma = match value {
+                        "420" | "420jpeg" | "420paldv" | "420mpeg2" => Some((2, 2)),
+                        "422" => Some((2, 1)),
+                        "444" => Some((1, 1)),
+                        "mono" => None,
+                        _ => return Err(format!("River can't play YUV4MPEG2 video with {} colors. Try adding -pix_fmt yuv420p to the ffmpeg command.", value)),
+                    };
+                },
+                "X" if value == "COLORRANGE=FULL" => video.full_range = true,
+                _ => {},
+            }
+        }
+        if video.width == 0 || video.height == 0 {
+            return Err("The YUV4MPEG2 header doesn't say how big the video is.".to_string());
+        }
+        if video.width > MAX_SIZE || video.height > MAX_SIZE {
+            return Err(format!("The video is {}x{}, but river can only play video up to {}x{}.", video.width, video.height, MAX_SIZE, MAX_SIZE));
+        }
+        Ok(video)
+    }
+}
+
+impl<R: Read> Iterator for Y4m<R> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let line = read_line(&mut self.reader)?;
+        if !line.starts_with("FRAME") {
+            eprintln!("Lost track of the frames in the YUV4MPEG2 stream, stopping.");
+            return None;
+        }
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut luma = vec![0; width*height];
+        self.reader.read_exact(&mut luma).ok()?;
+        let (cw, ch) = match self.chroma {
+            Some((sx, sy)) => (width.div_ceil(sx as usize), height.div_ceil(sy as usize)),
+            None => (0, 0),
+        };
+        let mut u = vec![128; cw*ch];
+        let mut v = vec![128; cw*ch];
+        self.reader.read_exact(&mut u).ok()?;
+        self.reader.read_exact(&mut v).ok()?;
+
+        let (sx, sy) = self.chroma.unwrap_or((1, 1));
+        let mut img = RgbImage::new(self.width, self.height);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let i = (y / sy) as usize * cw + (x / sx) as usize;
+            let (cb, cr) = if cw > 0 { (u[i], v[i]) } else { (128, 128) };
+            pixel.data = yuv_to_rgb(luma[y as usize * width + x as usize], cb, cr, self.full_range);
+        }
+        Some(Frame { image: DynamicImage::ImageRgb8(img), delay: self.delay })
+    }
+}
+
+// Raw frames of 8-bit red, green and blue, with nothing to say how big they
+// are or how fast they go, so the user has to tell us.
+pub struct Raw<R: Read> {
+    reader: BufReader<R>,
+    width: u32,
+    height: u32,
+    delay: f64,
+}
+
+impl<R: Read> Raw<R> {
+    pub fn new(reader: R, width: u32, height: u32, fps: f64) -> Raw<R> {
+        Raw { reader: BufReader::new(reader), width, height, delay: 1000.0 / fps }
+    }
+}
+
+impl<R: Read> Iterator for Raw<R> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let mut data = vec![0; (self.width*self.height*3) as usize];
+        self.reader.read_exact(&mut data).ok()?;
+        let img = RgbImage::from_raw(self.width, self.height, data)?;
+        Some(Frame { image: DynamicImage::ImageRgb8(img), delay: self.delay })
+    }
+}
+
+// Parse the size of raw frames, given as WIDTHxHEIGHT, up to MAX_SIZE each
+// way.
+pub fn parse_size(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, 'x').map(|n| n.trim().parse::<u32>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(w)), Some(Ok(h))) if w > 0 && h > 0 && w <= MAX_SIZE && h <= MAX_SIZE => Some((w, h)),
+        _ => None,
+    }
+}
+
+// Read a line of a header, without the line break. None means the stream
+// has ended.
+fn read_line<R: BufRead>(reader: &mut R) -> Option<String> {
+    let mut line = Vec::new();
+    match reader.read_until(b'\n', &mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            Some(String::from_utf8_lossy(&line).into_owned())
+        },
+    }
+}
+
+// Turn a pixel from video's YCbCr into RGB, with the BT.601 formula.
+fn yuv_to_rgb(y: u8, cb: u8, cr: u8, full_range: bool) -> [u8; 3] {
+    let (y, cb, cr) = if full_range {
+        (y as f32, cb as f32 - 128.0, cr as f32 - 128.0)
+    } else {
+        ((y as f32 - 16.0) * 255.0 / 219.0, (cb as f32 - 128.0) * 255.0 / 224.0, (cr as f32 - 128.0) * 255.0 / 224.0)
+    };
+    let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    [
+        clamp(y + 1.402*cr),
+        clamp(y - 0.344136*cb - 0.714136*cr),
+        clamp(y + 1.772*cb),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(text: &str) -> Result<Y4m<&[u8]>, String> {
+        Y4m::new(text.as_bytes())
+    }
+
+    #[test]
+    fn reads_the_header() {
+        let video = header("YUV4MPEG2 W640 H360 F30000:1001 Ip A1:1 C422 XYSCSS=422\n").unwrap();
+        assert_eq!((video.width, video.height, video.chroma, video.full_range), (640, 360, Some((2, 1)), false));
+        assert!((video.delay - 1001.0 / 30.0).abs() < 1e-9);
+
+        let video = header("YUV4MPEG2 W2 H2 Cmono XCOLORRANGE=FULL\n").unwrap();
+        assert_eq!((video.chroma, video.full_range, video.delay), (None, true, 40.0));
+        assert_eq!(header("YUV4MPEG2 W2 H2 C444\n").unwrap().chroma, Some((1, 1)));
+        assert_eq!(header("YUV4MPEG2 W2 H2 C420jpeg\n").unwrap().chroma, Some((2, 2)));
+        // A frame rate that makes no sense is left at the default.
+        assert_eq!(header("YUV4MPEG2 W2 H2 F0:0\n").unwrap().delay, 40.0);
+    }
+
+    #[test]
+    fn refuses_bad_headers() {
+        assert!(header("").is_err());
+        assert!(header("P6 W2 H2\n").is_err());
+        assert!(header("YUV4MPEG2 W2 H2 C420p10\n").err().unwrap().contains("420p10"));
+        assert!(header("YUV4MPEG2 W640\n").is_err());
+        assert!(header("YUV4MPEG2 Wlots H2\n").is_err());
+        assert!(header("YUV4MPEG2 W100000 H100000\n").is_err());
+        assert!(header(&format!("YUV4MPEG2 W{} H1\n", MAX_SIZE + 1)).is_err());
+        assert!(header(&format!("YUV4MPEG2 W{} H{}\n", MAX_SIZE, MAX_SIZE)).is_ok());
+    }
+
+    #[test]
+    fn reads_frames() {
+        // Two 2x2 frames with one color sample each, then half of a third.
+        let mut data = b"YUV4MPEG2 W2 H2 F25:1 C420jpeg\n".to_vec();
+        data.extend_from_slice(b"FRAME\n\x10\xeb\x10\xeb\x80\x80");
+        data.extend_from_slice(b"FRAME Ixyz\n\x51\x51\x51\x51\x5a\xf0");
+        data.extend_from_slice(b"FRAME\n\x10\x10");
+        let frames: Vec<Frame> = Y4m::new(&data[..]).unwrap().collect();
+        assert_eq!(frames.len(), 2);
+        let first = frames[0].image.to_rgb();
+        assert_eq!(first.get_pixel(0, 0).data, [0, 0, 0]);
+        assert_eq!(first.get_pixel(1, 1).data, [255, 255, 255]);
+        assert_eq!(frames[1].image.to_rgb().get_pixel(1, 0).data, [254, 0, 0]);
+        assert_eq!(frames[1].delay, 40.0);
+    }
+
+    #[test]
+    fn reads_raw_frames() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        let frames: Vec<Frame> = Raw::new(&data[..], 2, 1, 50.0).collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].image.to_rgb().into_raw(), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(frames[0].delay, 20.0);
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("640x360"), Some((640, 360)));
+        assert_eq!(parse_size(" 2 x 3 "), Some((2, 3)));
+        assert_eq!(parse_size("640"), None);
+        assert_eq!(parse_size("0x360"), None);
+        assert_eq!(parse_size("640x-1"), None);
+        assert_eq!(parse_size("640x360x2"), None);
+        assert_eq!(parse_size(&format!("{}x1", MAX_SIZE + 1)), None);
+        assert_eq!(parse_size("4294967295x4294967295"), None);
+    }
+
+    #[test]
+    fn converts_colors() {
+        assert_eq!(yuv_to_rgb(16, 128, 128, false), [0, 0, 0]);
+        assert_eq!(yuv_to_rgb(235, 128, 128, false), [255, 255, 255]);
+        assert_eq!(yuv_to_rgb(0, 128, 128, false), [0, 0, 0]);
+        assert_eq!(yuv_to_rgb(255, 128, 128, true), [255, 255, 255]);
+        assert_eq!(yuv_to_rgb(128, 128, 128, true), [128, 128, 128]);
+        // Pure red, green and blue in video range.
+        assert_eq!(yuv_to_rgb(81, 90, 240, false), [254, 0, 0]);
+        assert_eq!(yuv_to_rgb(145, 54, 34, false), [0, 255, 1]);
+        assert_eq!(yuv_to_rgb(41, 240, 110, false), [0, 0, 255]);
+    }
+}